@@ -45,7 +45,7 @@ fn main() {
         dump_files(&mut zip, where_to);
     }
 
-    if let Some(where_to) = args.extract_to {
+    if let Some(_where_to) = args.extract_to {
         // extract_files(&mut zip, where_to);
     }
 }
@@ -130,6 +130,7 @@ fn dump_files(zip: &mut ZipReader<&mut File>, where_to: PathBuf) {
     }
 }
 
+#[allow(dead_code)]
 fn check_crc<P: AsRef<Path>>(file: P) -> u32 {
     let buffer = &mut [0; 8192];
     let mut file = File::open(file).unwrap();
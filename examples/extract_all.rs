@@ -1,4 +1,8 @@
 use std::env::args;
+use std::path::Path;
+
+use ziplayer::compression_codecs::{CompressionCodec, NoCompressionCodec};
+use ziplayer::structures::CentralDirectory;
 
 fn main() {
     if args().len() != 3 {
@@ -16,13 +20,34 @@ fn main() {
     });
 
     // Canonicalize the output directory
-    let _output_dir = std::fs::canonicalize(output_dir).unwrap();
+    let output_dir = std::fs::canonicalize(output_dir).unwrap();
 
-    let deflate_codec = ziplayer::codecs::gzip_codec::new();
+    // Only stored entries can be expanded here, the entries compressed with
+    // another method are reported instead of failing the whole extraction.
+    let stored = NoCompressionCodec.int_id();
+    let unsupported = zip
+        .index()
+        .files()
+        .filter(|cd| cd.compression != stored)
+        .map(|cd| (cd.filename.clone(), cd.compression))
+        .collect::<Vec<_>>();
 
     // Extract all files
-    zip.extract_all_files(&output_dir, deflate_codec).unwrap_or_else(|e| {
-        println!("Error: ({:0X}):{}", e.error_code(), e);
+    let supported = |_: &Path, cd: &CentralDirectory| cd.compression == stored;
+    zip.extract_matching(&output_dir, &mut NoCompressionCodec, &supported)
+        .unwrap_or_else(|e| {
+            println!("Error: ({:0X}):{}", e.error_code(), e);
+            std::process::exit(1);
+        });
+
+    for (name, method) in &unsupported {
+        println!(
+            "Skipped {}: unsupported compression method {}",
+            name.display(),
+            method
+        );
+    }
+    if !unsupported.is_empty() {
         std::process::exit(1);
-    });
+    }
 }
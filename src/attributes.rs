@@ -0,0 +1,175 @@
+/*
+   Zip file reader and writer, in pure Rust.
+   Copyright (C) 2022 Matheus Xavier <mxavier@neonimp.com>
+
+   This program is free software: you can redistribute it and/or modify
+   it under the terms of the GNU Lesser General Public License as published by
+   the Free Software Foundation, either version 3 of the License, or
+   (at your option) any later version.
+
+   This program is distributed in the hope that it will be useful,
+   but WITHOUT ANY WARRANTY; without even the implied warranty of
+   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+   GNU General Public License for more details.
+
+   You should have received a copy of the GNU Lesser General Public License
+   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! File attributes of the entries.
+//!
+//! What the external attributes mean depends on the system that made the
+//! archive, stored in the upper byte of `version_made_by`. The low byte holds
+//! the MS-DOS attributes on every system, Unix-like systems also put the
+//! `st_mode` of the file in the upper 16 bits.
+
+/// MS-DOS directory attribute.
+pub const DOS_DIRECTORY: u32 = 0x10;
+
+/// File type mask of a Unix `st_mode`.
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFSOCK: u32 = 0o140000;
+pub const S_IFLNK: u32 = 0o120000;
+pub const S_IFREG: u32 = 0o100000;
+pub const S_IFBLK: u32 = 0o060000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFCHR: u32 = 0o020000;
+pub const S_IFIFO: u32 = 0o010000;
+
+/// The system that made the archive, from the upper byte of `version_made_by`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostOs {
+    MsDos,
+    Amiga,
+    OpenVms,
+    Unix,
+    VmCms,
+    AtariSt,
+    Os2Hpfs,
+    Macintosh,
+    ZSystem,
+    CpM,
+    WindowsNtfs,
+    Mvs,
+    Vse,
+    AcornRisc,
+    Vfat,
+    AlternateMvs,
+    BeOs,
+    Tandem,
+    Os400,
+    Osx,
+    Unknown(u8),
+}
+
+impl HostOs {
+    pub fn from_version_made_by(version_made_by: u16) -> Self {
+        match (version_made_by >> 8) as u8 {
+            0 => HostOs::MsDos,
+            1 => HostOs::Amiga,
+            2 => HostOs::OpenVms,
+            3 => HostOs::Unix,
+            4 => HostOs::VmCms,
+            5 => HostOs::AtariSt,
+            6 => HostOs::Os2Hpfs,
+            7 => HostOs::Macintosh,
+            8 => HostOs::ZSystem,
+            9 => HostOs::CpM,
+            10 => HostOs::WindowsNtfs,
+            11 => HostOs::Mvs,
+            12 => HostOs::Vse,
+            13 => HostOs::AcornRisc,
+            14 => HostOs::Vfat,
+            15 => HostOs::AlternateMvs,
+            16 => HostOs::BeOs,
+            17 => HostOs::Tandem,
+            18 => HostOs::Os400,
+            19 => HostOs::Osx,
+            other => HostOs::Unknown(other),
+        }
+    }
+
+    /// Whether this system stores a Unix `st_mode` in the upper 16 bits of the
+    /// external attributes.
+    pub fn has_unix_mode(&self) -> bool {
+        matches!(self, HostOs::Unix | HostOs::Osx)
+    }
+}
+
+/// The type of file an entry holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    Regular,
+    Directory,
+    Symlink,
+    Fifo,
+    CharDevice,
+    BlockDevice,
+    Socket,
+}
+
+impl FileType {
+    /// The file type of a Unix `st_mode`, `None` if the type bits are not set
+    /// or unknown.
+    pub fn from_unix_mode(mode: u32) -> Option<Self> {
+        Some(match mode & S_IFMT {
+            S_IFREG => FileType::Regular,
+            S_IFDIR => FileType::Directory,
+            S_IFLNK => FileType::Symlink,
+            S_IFIFO => FileType::Fifo,
+            S_IFCHR => FileType::CharDevice,
+            S_IFBLK => FileType::BlockDevice,
+            S_IFSOCK => FileType::Socket,
+            _ => return None,
+        })
+    }
+
+    /// The file type of the MS-DOS attributes, which only know directories.
+    pub fn from_dos_attributes(attributes: u32) -> Self {
+        if attributes & DOS_DIRECTORY != 0 {
+            FileType::Directory
+        } else {
+            FileType::Regular
+        }
+    }
+}
+
+/// The Unix `st_mode` in `external_file_attributes`, if the archive was made
+/// on a Unix-like system that filled it in.
+pub fn unix_mode(version_made_by: u16, external_file_attributes: u32) -> Option<u32> {
+    let mode = external_file_attributes >> 16;
+    (HostOs::from_version_made_by(version_made_by).has_unix_mode() && mode != 0).then_some(mode)
+}
+
+/// The file type from the external attributes, the Unix mode is preferred
+/// and the MS-DOS attributes are used when there's none.
+pub fn file_type(version_made_by: u16, external_file_attributes: u32) -> FileType {
+    unix_mode(version_made_by, external_file_attributes)
+        .and_then(FileType::from_unix_mode)
+        .unwrap_or_else(|| FileType::from_dos_attributes(external_file_attributes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_type() {
+        let unix = 3 << 8 | 30;
+        assert_eq!(HostOs::from_version_made_by(unix), HostOs::Unix);
+        assert_eq!(unix_mode(unix, 0o100644 << 16), Some(0o100644));
+        assert_eq!(file_type(unix, 0o100644 << 16), FileType::Regular);
+        assert_eq!(file_type(unix, 0o120777 << 16), FileType::Symlink);
+        assert_eq!(file_type(unix, 0o010600 << 16), FileType::Fifo);
+        assert_eq!(file_type(unix, 0o040755 << 16 | 0x10), FileType::Directory);
+        // No mode, only the MS-DOS attributes.
+        assert_eq!(file_type(unix, 0x10), FileType::Directory);
+
+        // The upper bits mean nothing for MS-DOS.
+        let dos = 20;
+        assert_eq!(HostOs::from_version_made_by(dos), HostOs::MsDos);
+        assert_eq!(unix_mode(dos, 0o120777 << 16), None);
+        assert_eq!(file_type(dos, 0o120777 << 16 | 0x20), FileType::Regular);
+        assert_eq!(HostOs::from_version_made_by(42 << 8), HostOs::Unknown(42));
+    }
+}
@@ -16,15 +16,21 @@ impl CompressionCodec for GzipCodec {
         14
     }
 
-    fn compress(&self, data: MemoryStream) -> crate::Result<Vec<u8>> {
-        todo!()
+    // Not implemented yet, fail instead of panicking. The default
+    // `expanding_reader` fails the same way.
+    fn compress(&self, _data: MemoryStream) -> crate::Result<Vec<u8>> {
+        Err(crate::ZipError::InvalidCompressionMethod(self.int_id()))
     }
 
-    fn expand(&self, data: MemoryStream) -> crate::Result<Vec<u8>> {
-        todo!()
+    fn expand(&self, _data: MemoryStream) -> crate::Result<Vec<u8>> {
+        Err(crate::ZipError::InvalidCompressionMethod(self.int_id()))
     }
 
-    fn streamed_expansion(&self, reader: &mut impl BufRead, writer: &mut impl Write) {
-        todo!()
+    fn streamed_expansion(
+        &self,
+        _reader: &mut impl BufRead,
+        _writer: &mut impl Write,
+    ) -> crate::Result<()> {
+        Err(crate::ZipError::InvalidCompressionMethod(self.int_id()))
     }
 }
@@ -36,8 +36,13 @@ impl CompressionCodec for ZstdCodec {
         Ok(buf)
     }
 
-    fn streamed_expansion(&self, reader: &mut impl BufRead, writer: &mut impl Write) {
-        let mut decoder = Decoder::new(reader).unwrap();
-        std::io::copy(&mut decoder, writer).unwrap();
+    fn streamed_expansion(&self, reader: &mut impl BufRead, writer: &mut impl Write) -> Result<()> {
+        let mut decoder = Decoder::new(reader)?;
+        std::io::copy(&mut decoder, writer)?;
+        Ok(())
+    }
+
+    fn expanding_reader<'r>(&self, reader: Box<dyn BufRead + 'r>) -> Result<Box<dyn Read + 'r>> {
+        Ok(Box::new(Decoder::with_buffer(reader)?))
     }
 }
@@ -0,0 +1,583 @@
+/*
+   Zip file reader and writer, in pure Rust.
+   Copyright (C) 2022 Matheus Xavier <mxavier@neonimp.com>
+
+   This program is free software: you can redistribute it and/or modify
+   it under the terms of the GNU Lesser General Public License as published by
+   the Free Software Foundation, either version 3 of the License, or
+   (at your option) any later version.
+
+   This program is distributed in the hope that it will be useful,
+   but WITHOUT ANY WARRANTY; without even the implied warranty of
+   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+   GNU General Public License for more details.
+
+   You should have received a copy of the GNU Lesser General Public License
+   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! A compact index for archives with millions of entries.
+//!
+//! [`crate::index::ZipIndex`] keeps every central directory record whole,
+//! with a few heap allocations each. The compact index keeps the names in a
+//! single string and a fixed-size record per entry, sorted by name for binary
+//! search. The extra fields and comments are read from the archive again when
+//! they are asked for.
+
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::compression_codecs::CompressionCodec;
+use crate::datetime::TimeZone;
+use crate::encoding::{Cp437, LegacyDecoder};
+use crate::index::DuplicatePolicy;
+use crate::index_cache::{
+    read_compact_index_cache, write_cache_file, write_compact_index_cache, ArchiveFingerprint,
+};
+use crate::reader::{
+    dump_file, entry_comment, index_archive_compact, load_central_dir, locate_central_dir,
+    open_entry, CentralDirLocation, ZipEntryInfo, ZipEntryReader,
+};
+use crate::structures::CentralDirectory;
+use crate::{Result, ZipError};
+
+/// The fixed-size part of a central directory record, the name is stored in
+/// the [`CompactIndex`] that holds the record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactEntry {
+    pub(crate) name_start: u64,
+    pub(crate) name_len: u32,
+    /// Where the central directory record is in the stream.
+    pub cd_offset: u64,
+    /// Where the local header is in the stream, across volumes.
+    pub local_header_offset: u64,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+    pub crc32: u32,
+    pub external_file_attributes: u32,
+    pub version_made_by: u16,
+    pub flags: u16,
+    pub compression: u16,
+    pub last_mod_time: u16,
+    pub last_mod_date: u16,
+    pub is_directory: bool,
+}
+
+/// The entries of an archive, with their names in one string and their
+/// records sorted by name, see [`CompactEntry`].
+///
+/// Records sharing a name are kept in archive order, the lookups return one
+/// of them according to the [`DuplicatePolicy`].
+#[derive(Debug, Clone, Default)]
+pub struct CompactIndex {
+    names: String,
+    records: Vec<CompactEntry>,
+    /// The number of distinct names.
+    distinct: usize,
+    policy: DuplicatePolicy,
+}
+
+impl CompactIndex {
+    /// Make room for `additional` more records.
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        self.records.reserve(additional);
+    }
+
+    /// Rebuild an index from the parts returned by [`CompactIndex::parts`],
+    /// the records must already be sorted. The parts don't include the
+    /// duplicate policy, the index starts with the default.
+    pub(crate) fn from_parts(names: String, records: Vec<CompactEntry>, distinct: usize) -> Self {
+        CompactIndex {
+            names,
+            records,
+            distinct,
+            policy: DuplicatePolicy::default(),
+        }
+    }
+
+    /// The names, the sorted records and the number of distinct names.
+    pub(crate) fn parts(&self) -> (&str, &[CompactEntry], usize) {
+        (&self.names, &self.records, self.distinct)
+    }
+
+    /// Add the fixed-size part of `entry`, [`CompactIndex::sort`] must be
+    /// called once all the entries are in.
+    pub(crate) fn push(&mut self, entry: &CentralDirectory) {
+        let name = entry.filename.to_string_lossy();
+        let name_start = self.names.len() as u64;
+        self.names.push_str(&name);
+        self.records.push(CompactEntry {
+            name_start,
+            name_len: name.len() as u32,
+            cd_offset: entry.offset,
+            local_header_offset: entry.local_header_rel_offset,
+            compressed_size: entry.compressed_size,
+            uncompressed_size: entry.uncompressed_size,
+            crc32: entry.crc32,
+            external_file_attributes: entry.external_file_attributes,
+            version_made_by: entry.version_made_by,
+            flags: entry.flags,
+            compression: entry.compression,
+            last_mod_time: entry.last_mod_time,
+            last_mod_date: entry.last_mod_date,
+            is_directory: entry.is_directory,
+        });
+    }
+
+    /// Sort the records by name, the sort is stable so duplicates stay in
+    /// archive order.
+    pub(crate) fn sort(&mut self) {
+        let names = &self.names;
+        self.records
+            .sort_by(|a, b| cmp_names(name_of(names, a), name_of(names, b)));
+        self.distinct = self.groups().count();
+        self.names.shrink_to_fit();
+        self.records.shrink_to_fit();
+    }
+
+    pub fn duplicate_policy(&self) -> DuplicatePolicy {
+        self.policy
+    }
+
+    /// Choose which record the lookups return for duplicate names.
+    pub fn set_duplicate_policy(&mut self, policy: DuplicatePolicy) {
+        self.policy = policy;
+    }
+
+    /// The name of `entry`, which must come from this index.
+    pub fn name(&self, entry: &CompactEntry) -> &str {
+        name_of(&self.names, entry)
+    }
+
+    fn pick<'a>(&self, group: &'a [CompactEntry]) -> &'a CompactEntry {
+        match self.policy {
+            DuplicatePolicy::FirstWins => &group[0],
+            DuplicatePolicy::LastWins => &group[group.len() - 1],
+        }
+    }
+
+    /// The records sharing each name.
+    fn groups(&self) -> impl Iterator<Item = &[CompactEntry]> {
+        self.records
+            .chunk_by(|a, b| cmp_names(self.name(a), self.name(b)).is_eq())
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&CompactEntry> {
+        let group = self.get_all(path);
+        (!group.is_empty()).then(|| self.pick(group))
+    }
+
+    /// Every record named `path`, in archive order.
+    pub fn get_all(&self, path: &Path) -> &[CompactEntry] {
+        let path = path.to_string_lossy();
+        let start = self
+            .records
+            .partition_point(|entry| cmp_names(self.name(entry), &path).is_lt());
+        let len = self.records[start..]
+            .partition_point(|entry| cmp_names(self.name(entry), &path).is_eq());
+        &self.records[start..start + len]
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        !self.get_all(path).is_empty()
+    }
+
+    /// The number of distinct names, see [`CompactIndex::entries`] for the
+    /// number of records.
+    pub fn len(&self) -> usize {
+        self.distinct
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Every record, duplicates included, sorted by name.
+    pub fn entries(&self) -> &[CompactEntry] {
+        &self.records
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &CompactEntry)> {
+        self.groups().map(|group| {
+            let entry = self.pick(group);
+            (self.name(entry), entry)
+        })
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = (&str, &CompactEntry)> {
+        self.iter().filter(|(_, entry)| !entry.is_directory)
+    }
+
+    pub fn dirs(&self) -> impl Iterator<Item = (&str, &CompactEntry)> {
+        self.iter().filter(|(_, entry)| entry.is_directory)
+    }
+}
+
+/// Write `index` to the sidecar file at `path`, see
+/// [`write_compact_index_cache`].
+fn save_index(path: &Path, index: &CompactIndex, fingerprint: &ArchiveFingerprint) -> Result<()> {
+    write_cache_file(path, |writer| {
+        write_compact_index_cache(writer, index, fingerprint)
+    })
+}
+
+/// Compare two names component by component without parsing them into
+/// paths, names are equal exactly when their [`Path`]s are.
+fn cmp_names(a: &str, b: &str) -> Ordering {
+    if !is_plain(a) || !is_plain(b) {
+        return components(a).cmp(components(b));
+    }
+    // Plain names compare byte by byte once the separator sorts first.
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    match a.iter().zip(b).position(|(x, y)| x != y) {
+        Some(i) => sort_key(a[i]).cmp(&sort_key(b[i])),
+        None => a.len().cmp(&b.len()),
+    }
+}
+
+fn sort_key(byte: u8) -> u16 {
+    if byte == b'/' {
+        0
+    } else {
+        u16::from(byte) + 1
+    }
+}
+
+/// Whether every `/` in the name separates two components, as in almost all
+/// archive names.
+fn is_plain(name: &str) -> bool {
+    name != "."
+        && !name.starts_with("./")
+        && !name.ends_with('/')
+        && !name.ends_with("/.")
+        && !name.contains("//")
+        && !name.contains("/./")
+}
+
+/// The components of a name like [`Path::components`] splits them, with an
+/// empty first component for the root and a leading `.` kept.
+fn components(name: &str) -> impl Iterator<Item = &str> {
+    let root = if name.starts_with('/') {
+        Some("")
+    } else if name == "." || name.starts_with("./") {
+        Some(".")
+    } else {
+        None
+    };
+    let rest = name
+        .split('/')
+        .filter(|component| !component.is_empty() && *component != ".");
+    root.into_iter().chain(rest)
+}
+
+fn name_of<'a>(names: &'a str, entry: &CompactEntry) -> &'a str {
+    let start = entry.name_start as usize;
+    &names[start..start + entry.name_len as usize]
+}
+
+/// A reader over an archive indexed with a [`CompactIndex`], for archives too
+/// large to keep every central directory record in memory.
+///
+/// The lookups read the whole record of the entry from the archive again,
+/// which costs a seek per lookup.
+pub struct CompactZipReader<R: Read + Seek> {
+    reader: BufReader<R>,
+    index: CompactIndex,
+    /// Where the end of central directory record is in the stream.
+    eocd_offset: u64,
+    /// Where the central directory is in the stream.
+    central_dir: Range<u64>,
+    verify_crc: bool,
+    legacy_decoder: Box<dyn LegacyDecoder>,
+    time_zone: TimeZone,
+    /// Why the sidecar given to [`CompactZipReader::new_cached`] couldn't be
+    /// written.
+    cache_error: Option<ZipError>,
+}
+
+impl<R: Read + Seek> CompactZipReader<R> {
+    /// Read and index a ZIP archive.
+    pub fn new(reader: R) -> Result<Self> {
+        Self::with_legacy_decoder(reader, Cp437)
+    }
+
+    /// Read and index a ZIP archive, decoding the names that are not flagged
+    /// as UTF-8 with `decoder`, see [`crate::reader::ZipReader::set_legacy_decoder`].
+    pub fn with_legacy_decoder(reader: R, decoder: impl LegacyDecoder + 'static) -> Result<Self> {
+        let mut reader = BufReader::new(reader);
+        let location = locate_central_dir(&mut reader, None)?;
+        let index = index_archive_compact(
+            &mut reader,
+            location.cd_offset,
+            location.entries,
+            location.cd_size,
+            &location.volume_starts,
+            &decoder,
+        )?;
+        Ok(Self::from_index(reader, location, index, decoder))
+    }
+
+    /// Read an archive with the index saved by [`CompactZipReader::save_index`]
+    /// in the sidecar file at `cache`, see
+    /// [`crate::reader::ZipReader::new_cached`].
+    pub fn new_cached<P: AsRef<Path>>(reader: R, cache: P) -> Result<Self> {
+        Self::new_cached_with_legacy_decoder(reader, cache, Cp437)
+    }
+
+    /// Like [`CompactZipReader::new_cached`], decoding the names with
+    /// `decoder` like [`CompactZipReader::with_legacy_decoder`]. The sidecar
+    /// must be read with the decoder it was written with.
+    pub fn new_cached_with_legacy_decoder<P: AsRef<Path>>(
+        reader: R,
+        cache: P,
+        decoder: impl LegacyDecoder + 'static,
+    ) -> Result<Self> {
+        let mut reader = BufReader::new(reader);
+        let location = locate_central_dir(&mut reader, None)?;
+        let fingerprint = ArchiveFingerprint::compute(
+            &mut reader,
+            location.eocd.offset,
+            location.cd_offset,
+            location.cd_size,
+        )?;
+        let cached = File::open(cache.as_ref())
+            .map_err(ZipError::from)
+            .and_then(|file| read_compact_index_cache(&mut BufReader::new(file), &fingerprint));
+        let mut cache_error = None;
+        let index = match cached {
+            Ok(Some(index)) => index,
+            _ => {
+                let index = index_archive_compact(
+                    &mut reader,
+                    location.cd_offset,
+                    location.entries,
+                    location.cd_size,
+                    &location.volume_starts,
+                    &decoder,
+                )?;
+                cache_error = save_index(cache.as_ref(), &index, &fingerprint).err();
+                index
+            }
+        };
+        let mut zip = Self::from_index(reader, location, index, decoder);
+        zip.cache_error = cache_error;
+        Ok(zip)
+    }
+
+    fn from_index(
+        reader: BufReader<R>,
+        location: CentralDirLocation,
+        index: CompactIndex,
+        decoder: impl LegacyDecoder + 'static,
+    ) -> Self {
+        CompactZipReader {
+            reader,
+            index,
+            eocd_offset: location.eocd.offset,
+            central_dir: location.cd_offset..location.cd_offset + location.cd_size,
+            verify_crc: true,
+            legacy_decoder: Box::new(decoder),
+            time_zone: TimeZone::default(),
+            cache_error: None,
+        }
+    }
+
+    /// What ties the index to this archive, see
+    /// [`CompactZipReader::save_index`].
+    pub fn fingerprint(&mut self) -> Result<ArchiveFingerprint> {
+        ArchiveFingerprint::compute(
+            &mut self.reader,
+            self.eocd_offset,
+            self.central_dir.start,
+            self.central_dir.end - self.central_dir.start,
+        )
+    }
+
+    /// Save the index to the sidecar file at `path`, to reopen the archive
+    /// with [`CompactZipReader::new_cached`] without parsing the central
+    /// directory. The names are saved as they were decoded, the duplicate
+    /// policy is not saved and must be set again after reopening.
+    pub fn save_index<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let fingerprint = self.fingerprint()?;
+        save_index(path.as_ref(), &self.index, &fingerprint)
+    }
+
+    /// Why [`CompactZipReader::new_cached`] couldn't write the sidecar, `None`
+    /// if it was written or read.
+    pub fn cache_error(&self) -> Option<&ZipError> {
+        self.cache_error.as_ref()
+    }
+
+    pub fn index(&self) -> &CompactIndex {
+        &self.index
+    }
+
+    /// Choose which entry is used when several have the same name, the last
+    /// one by default, see [`DuplicatePolicy`].
+    pub fn set_duplicate_policy(&mut self, policy: DuplicatePolicy) {
+        self.index.set_duplicate_policy(policy);
+    }
+
+    /// Choose whether extracted data is checked against the CRC-32 stored in
+    /// the archive, this is on by default and can be turned off for speed.
+    pub fn set_verify_crc(&mut self, verify: bool) {
+        self.verify_crc = verify;
+    }
+
+    /// Choose the time zone the DOS timestamps of the entries are interpreted
+    /// in, this is the local time zone by default.
+    pub fn set_time_zone(&mut self, time_zone: TimeZone) {
+        self.time_zone = time_zone;
+    }
+
+    /// Read the whole central directory record of `filename` from the
+    /// archive, with its extra field and comment.
+    pub fn central_dir<T: AsRef<Path>>(&mut self, filename: &T) -> Result<CentralDirectory> {
+        let entry = *self
+            .index
+            .get(filename.as_ref())
+            .ok_or(ZipError::EntryNotFound(filename.as_ref().into()))?;
+        load_central_dir(&mut self.reader, &entry, &*self.legacy_decoder)
+    }
+
+    pub fn file_info<T: AsRef<Path>>(&mut self, filename: &T) -> Result<ZipEntryInfo> {
+        let entry = self.central_dir(filename)?;
+        Ok(ZipEntryInfo::from_central_dir(
+            &entry,
+            &*self.legacy_decoder,
+            self.time_zone,
+        ))
+    }
+
+    /// The decoded comment of an entry, `None` if it has none.
+    pub fn entry_comment<T: AsRef<Path>>(&mut self, filename: &T) -> Result<Option<String>> {
+        let entry = self.central_dir(filename)?;
+        Ok(entry_comment(&entry, &*self.legacy_decoder))
+    }
+
+    /// Dump a file from the archive, without decompressing it.
+    pub fn dump_file<T: AsRef<Path>>(&mut self, filename: &T) -> Result<Vec<u8>> {
+        let entry = self.central_dir(filename)?;
+        dump_file(&mut self.reader, &entry)
+    }
+
+    /// Open a reader that expands the file with `codec` as it's read.
+    pub fn open_entry<T: AsRef<Path>>(
+        &mut self,
+        filename: &T,
+        codec: &impl CompressionCodec,
+    ) -> Result<ZipEntryReader<'_>> {
+        let entry = self.central_dir(filename)?;
+        Ok(open_entry(&mut self.reader, &entry, codec)?.verify_crc(self.verify_crc))
+    }
+
+    /// Extract a file from the archive.
+    pub fn extract_file<T: AsRef<Path>>(
+        &mut self,
+        filename: &T,
+        codec: &impl CompressionCodec,
+    ) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.open_entry(filename, codec)?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+}
+
+impl CompactZipReader<File> {
+    /// Open and index the archive at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::new(File::open(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression_codecs::NoCompressionCodec;
+    use crate::test_util::{TestArchive, TestEntry};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_cmp_names() {
+        let names = [
+            "a", "a-b", "a/b", "a//b", "a/./b", "./a", ".", "/a", "a/", "a b", "a\0", "",
+        ];
+        for a in names {
+            for b in names {
+                let expected = Path::new(a) == Path::new(b);
+                assert_eq!(cmp_names(a, b).is_eq(), expected, "{a:?} {b:?}");
+                if is_plain(a) && is_plain(b) {
+                    assert_eq!(cmp_names(a, b), Path::new(a).cmp(Path::new(b)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_compact_index() {
+        assert_eq!(std::mem::size_of::<CompactEntry>(), 64);
+        let data = TestArchive::new()
+            .add("b/c.txt", b"hello")
+            .add("a.txt", b"first")
+            .add("b/", b"")
+            .add_entry(TestEntry {
+                name: "a.txt",
+                contents: b"second",
+                comment: b"the last one",
+                ..Default::default()
+            })
+            .finish();
+        let mut zip = CompactZipReader::new(Cursor::new(data)).unwrap();
+        let index = zip.index();
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.entries().len(), 4);
+        let names = index.iter().map(|(name, _)| name).collect::<Vec<_>>();
+        assert_eq!(names, ["a.txt", "b/", "b/c.txt"]);
+        assert!(index.get(Path::new("b")).unwrap().is_directory);
+        assert_eq!(index.get_all(Path::new("a.txt")).len(), 2);
+        assert!(!index.contains(Path::new("c.txt")));
+
+        let codec = NoCompressionCodec;
+        assert_eq!(zip.extract_file(&"a.txt", &codec).unwrap(), b"second");
+        assert_eq!(zip.extract_file(&"b/c.txt", &codec).unwrap(), b"hello");
+        assert_eq!(
+            zip.entry_comment(&"a.txt").unwrap().as_deref(),
+            Some("the last one")
+        );
+        zip.set_duplicate_policy(DuplicatePolicy::FirstWins);
+        assert_eq!(zip.dump_file(&"a.txt").unwrap(), b"first");
+        assert_eq!(zip.entry_comment(&"a.txt").unwrap(), None);
+        assert!(matches!(
+            zip.central_dir(&"missing"),
+            Err(ZipError::EntryNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_crafted_entry_count() {
+        let data = TestArchive::new().add("a.txt", b"hello").finish_zip64();
+        let eocd64_at = data.len() - 22 - 20 - 56;
+        let cd_size = u64::from_le_bytes(data[eocd64_at + 40..eocd64_at + 48].try_into().unwrap());
+
+        // Far more entries than the central directory can hold.
+        let mut crafted = data.clone();
+        crafted[eocd64_at + 32..eocd64_at + 40].copy_from_slice(&u64::MAX.to_le_bytes());
+        let too_short = ZipError::CentralDirectorySizeMismatch(cd_size, u64::MAX);
+        assert_eq!(
+            CompactZipReader::new(Cursor::new(crafted.clone())).err(),
+            Some(too_short)
+        );
+        assert!(crate::reader::ZipReader::new(Cursor::new(crafted)).is_err());
+
+        // A central directory larger than the archive.
+        let mut crafted = data;
+        crafted[eocd64_at + 40..eocd64_at + 48].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(
+            CompactZipReader::new(Cursor::new(crafted)),
+            Err(ZipError::CentralDirectorySizeMismatch(u64::MAX, _))
+        ));
+    }
+}
@@ -23,12 +23,22 @@ pub trait CompressionCodec: Sync + Send {
         self.expand((&buf, buf.len()))
     }
 
-    fn streamed_expansion(&self, reader: &mut impl BufRead, writer: &mut impl Write);
+    fn streamed_expansion(&self, reader: &mut impl BufRead, writer: &mut impl Write) -> Result<()>;
+
+    /// Wrap `reader` so that reading from the result yields the expanded data,
+    /// this lets callers pull the data through without holding all of it.
+    ///
+    /// Codecs that can't expand as they go don't support it, the default
+    /// fails with [`crate::ZipError::InvalidCompressionMethod`] instead of
+    /// buffering the whole entry in memory.
+    fn expanding_reader<'r>(&self, _reader: Box<dyn BufRead + 'r>) -> Result<Box<dyn Read + 'r>> {
+        Err(crate::ZipError::InvalidCompressionMethod(self.int_id()))
+    }
 }
 
 /// No compression codec.
 /// Just returns the data as is.
-struct NoCompressionCodec;
+pub struct NoCompressionCodec;
 
 impl CompressionCodec for NoCompressionCodec {
     fn int_id(&self) -> u16 {
@@ -43,7 +53,12 @@ impl CompressionCodec for NoCompressionCodec {
         Ok(data.0.to_vec())
     }
 
-    fn streamed_expansion(&self, reader: &mut impl BufRead, writer: &mut impl Write) {
-        std::io::copy(reader, writer).unwrap();
+    fn streamed_expansion(&self, reader: &mut impl BufRead, writer: &mut impl Write) -> Result<()> {
+        std::io::copy(reader, writer)?;
+        Ok(())
+    }
+
+    fn expanding_reader<'r>(&self, reader: Box<dyn BufRead + 'r>) -> Result<Box<dyn Read + 'r>> {
+        Ok(reader)
     }
 }
@@ -0,0 +1,235 @@
+/*
+   Zip file reader and writer, in pure Rust.
+   Copyright (C) 2022 Matheus Xavier <mxavier@neonimp.com>
+
+   This program is free software: you can redistribute it and/or modify
+   it under the terms of the GNU Lesser General Public License as published by
+   the Free Software Foundation, either version 3 of the License, or
+   (at your option) any later version.
+
+   This program is distributed in the hope that it will be useful,
+   but WITHOUT ANY WARRANTY; without even the implied warranty of
+   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+   GNU General Public License for more details.
+
+   You should have received a copy of the GNU Lesser General Public License
+   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! MS-DOS date and time, the only timestamp every zip header has.
+//!
+//! It counts years from 1980, has a 2 second resolution and no time zone, the
+//! tool that wrote the archive normally used the local time of its machine.
+
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How to place a [`DosDateTime`] on the timeline, since it has no time zone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimeZone {
+    /// The local time zone of this machine, like most tools do. Only
+    /// available on Unix with the `libc` feature, UTC is used otherwise.
+    #[default]
+    Local,
+    Utc,
+    /// A fixed offset in seconds east of UTC.
+    Fixed(i32),
+}
+
+/// A date and time as stored in the `last_mod_date` and `last_mod_time`
+/// header fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DosDateTime {
+    pub date: u16,
+    pub time: u16,
+}
+
+impl DosDateTime {
+    pub fn new(date: u16, time: u16) -> Self {
+        DosDateTime { date, time }
+    }
+
+    pub fn year(&self) -> u16 {
+        1980 + (self.date >> 9)
+    }
+
+    pub fn month(&self) -> u8 {
+        (self.date >> 5 & 0x0F) as u8
+    }
+
+    pub fn day(&self) -> u8 {
+        (self.date & 0x1F) as u8
+    }
+
+    pub fn hour(&self) -> u8 {
+        (self.time >> 11) as u8
+    }
+
+    pub fn minute(&self) -> u8 {
+        (self.time >> 5 & 0x3F) as u8
+    }
+
+    /// Always even, the field holds the seconds divided by two.
+    pub fn second(&self) -> u8 {
+        (self.time & 0x1F) as u8 * 2
+    }
+
+    /// Whether the fields make up a real date and time, zeroed or garbage
+    /// timestamps are common in archives made by careless tools.
+    pub fn is_valid(&self) -> bool {
+        (1..=12).contains(&self.month())
+            && self.day() >= 1
+            && self.day() <= days_in_month(self.year(), self.month())
+            && self.hour() < 24
+            && self.minute() < 60
+            && self.second() < 60
+    }
+
+    /// Convert to a [`SystemTime`], interpreting the fields in `time_zone`.
+    /// Returns `None` if the date and time are not valid.
+    pub fn to_system_time(&self, time_zone: TimeZone) -> Option<SystemTime> {
+        if !self.is_valid() {
+            return None;
+        }
+        let utc_offset = match time_zone {
+            TimeZone::Utc => 0,
+            TimeZone::Fixed(offset) => offset as i64,
+            TimeZone::Local => return self.local_time(),
+        };
+        from_unix_secs(self.as_utc_secs() - utc_offset)
+    }
+
+    /// Seconds since the epoch, if the fields were UTC.
+    fn as_utc_secs(&self) -> i64 {
+        let days = days_from_civil(self.year() as i64, self.month() as i64, self.day() as i64);
+        days * 86400 + self.hour() as i64 * 3600 + self.minute() as i64 * 60 + self.second() as i64
+    }
+
+    #[cfg(all(unix, feature = "libc"))]
+    fn local_time(&self) -> Option<SystemTime> {
+        // SAFETY: tm is plain data and mktime only reads and normalizes it.
+        let secs = unsafe {
+            let mut tm: libc::tm = std::mem::zeroed();
+            tm.tm_year = self.year() as i32 - 1900;
+            tm.tm_mon = self.month() as i32 - 1;
+            tm.tm_mday = self.day() as i32;
+            tm.tm_hour = self.hour() as i32;
+            tm.tm_min = self.minute() as i32;
+            tm.tm_sec = self.second() as i32;
+            tm.tm_isdst = -1;
+            libc::mktime(&mut tm)
+        };
+        if secs == -1 {
+            return None;
+        }
+        from_unix_secs(secs as i64)
+    }
+
+    #[cfg(not(all(unix, feature = "libc")))]
+    fn local_time(&self) -> Option<SystemTime> {
+        from_unix_secs(self.as_utc_secs())
+    }
+}
+
+impl fmt::Display for DosDateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year(),
+            self.month(),
+            self.day(),
+            self.hour(),
+            self.minute(),
+            self.second()
+        )
+    }
+}
+
+fn is_leap_year(year: u16) -> bool {
+    year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400))
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date, from Howard Hinnant's
+/// `days_from_civil`.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_from_march = (month + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The [`SystemTime`] `secs` seconds away from the epoch, in either direction.
+pub(crate) fn from_unix_secs(secs: i64) -> Option<SystemTime> {
+    if secs >= 0 {
+        UNIX_EPOCH.checked_add(Duration::from_secs(secs as u64))
+    } else {
+        UNIX_EPOCH.checked_sub(Duration::from_secs(secs.unsigned_abs()))
+    }
+}
+
+/// 100 ns intervals between 1601-01-01, the NTFS epoch, and the Unix epoch.
+const FILETIME_UNIX_EPOCH: u64 = 116_444_736_000_000_000;
+
+/// Convert an NTFS FILETIME, in 100 ns intervals since 1601-01-01 UTC, to a
+/// [`SystemTime`]. Zero means the time was not set.
+pub(crate) fn from_filetime(filetime: u64) -> Option<SystemTime> {
+    if filetime == 0 {
+        return None;
+    }
+    let since_epoch = |intervals: u64| {
+        Duration::new(
+            intervals / 10_000_000,
+            (intervals % 10_000_000) as u32 * 100,
+        )
+    };
+    if filetime >= FILETIME_UNIX_EPOCH {
+        UNIX_EPOCH.checked_add(since_epoch(filetime - FILETIME_UNIX_EPOCH))
+    } else {
+        UNIX_EPOCH.checked_sub(since_epoch(FILETIME_UNIX_EPOCH - filetime))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dos_date_time() {
+        // 2022-10-29 13:45:58
+        let dos = DosDateTime::new((42 << 9) | (10 << 5) | 29, (13 << 11) | (45 << 5) | 29);
+        assert!(dos.is_valid());
+        assert_eq!(dos.to_string(), "2022-10-29 13:45:58");
+        assert_eq!(
+            dos.to_system_time(TimeZone::Utc),
+            Some(UNIX_EPOCH + Duration::from_secs(1667051158))
+        );
+        assert_eq!(
+            dos.to_system_time(TimeZone::Fixed(3600)),
+            Some(UNIX_EPOCH + Duration::from_secs(1667051158 - 3600))
+        );
+    }
+
+    #[test]
+    fn test_invalid_dos_date_time() {
+        assert!(!DosDateTime::new(0, 0).is_valid());
+        // 2021-02-29
+        assert!(!DosDateTime::new((41 << 9) | (2 << 5) | 29, 0).is_valid());
+        // 2020-02-29 is fine, but not at 24:00
+        assert!(DosDateTime::new((40 << 9) | (2 << 5) | 29, 0).is_valid());
+        assert!(!DosDateTime::new((40 << 9) | (2 << 5) | 29, 24 << 11).is_valid());
+        assert_eq!(DosDateTime::new(0, 0).to_system_time(TimeZone::Utc), None);
+    }
+}
@@ -0,0 +1,170 @@
+/*
+   Zip file reader and writer, in pure Rust.
+   Copyright (C) 2022 Matheus Xavier <mxavier@neonimp.com>
+
+   This program is free software: you can redistribute it and/or modify
+   it under the terms of the GNU Lesser General Public License as published by
+   the Free Software Foundation, either version 3 of the License, or
+   (at your option) any later version.
+
+   This program is distributed in the hope that it will be useful,
+   but WITHOUT ANY WARRANTY; without even the implied warranty of
+   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+   GNU General Public License for more details.
+
+   You should have received a copy of the GNU Lesser General Public License
+   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Decoding of file names and comments.
+//!
+//! The spec says names are UTF-8 when bit 11 of the flags is set and IBM code
+//! page 437 otherwise, but plenty of tools just use the code page of the
+//! machine they ran on, so the legacy encoding can be swapped out.
+//!
+//! Some of those tools also store the UTF-8 name or comment in an Info-ZIP
+//! Unicode extra field, which is preferred when it still matches the header.
+
+use crate::extra_field::{ExtraField, ExtraFields};
+use crate::{UNICODE_COMMENT_EXTRA_ID, UNICODE_PATH_EXTRA_ID, ZIP_CRC};
+
+/// Bit 11 of the general purpose flags, set when names and comments are UTF-8.
+pub const UTF8_FLAG: u16 = 1 << 11;
+
+/// Decodes names and comments that are not flagged as UTF-8.
+///
+/// Implemented for closures, so any encoding library can be plugged in, for
+/// example to read Shift-JIS or GBK archives.
+pub trait LegacyDecoder: Send + Sync {
+    fn decode(&self, bytes: &[u8]) -> String;
+}
+
+impl<F: Fn(&[u8]) -> String + Send + Sync> LegacyDecoder for F {
+    fn decode(&self, bytes: &[u8]) -> String {
+        self(bytes)
+    }
+}
+
+/// IBM code page 437, the encoding the spec mandates when the UTF-8 flag is
+/// not set.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Cp437;
+
+/// The upper half of code page 437, the lower half is plain ASCII.
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+impl LegacyDecoder for Cp437 {
+    fn decode(&self, bytes: &[u8]) -> String {
+        bytes
+            .iter()
+            .map(|&b| match b {
+                0x00..=0x7F => b as char,
+                _ => CP437_HIGH[(b - 0x80) as usize],
+            })
+            .collect()
+    }
+}
+
+/// Decode a name or comment according to `flags`, UTF-8 if the flag is set,
+/// `legacy` otherwise. Invalid UTF-8 is replaced rather than rejected so the
+/// archive can still be read, the raw bytes are kept alongside anyway.
+pub fn decode_text(raw: &[u8], flags: u16, legacy: &dyn LegacyDecoder) -> String {
+    if flags & UTF8_FLAG != 0 {
+        String::from_utf8_lossy(raw).into_owned()
+    } else {
+        legacy.decode(raw)
+    }
+}
+
+/// Where the decoded name or comment of an entry came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameSource {
+    /// The header, flagged as UTF-8.
+    Utf8,
+    /// The header, decoded with the legacy decoder.
+    Legacy,
+    /// The Info-ZIP Unicode Path or Comment extra field.
+    UnicodeExtra,
+}
+
+/// Decode a name or comment, preferring the Info-ZIP Unicode extra field `id`
+/// in `extra` over the header value `raw`.
+///
+/// The extra field holds the CRC-32 of the header value it was made for, it's
+/// ignored if that doesn't match, since the header was then changed by a tool
+/// that didn't know about the extra field.
+pub fn decode_with_extra(
+    raw: &[u8],
+    flags: u16,
+    extra: &[u8],
+    id: u16,
+    legacy: &dyn LegacyDecoder,
+) -> (String, NameSource) {
+    let crc32 = ZIP_CRC.checksum(raw);
+    let unicode = ExtraFields::new(extra).find_map(|field| match field {
+        ExtraField::UnicodePath {
+            version: 1,
+            crc32: field_crc,
+            name: text,
+        } if id == UNICODE_PATH_EXTRA_ID && field_crc == crc32 => Some(text),
+        ExtraField::UnicodeComment {
+            version: 1,
+            crc32: field_crc,
+            comment: text,
+        } if id == UNICODE_COMMENT_EXTRA_ID && field_crc == crc32 => Some(text),
+        _ => None,
+    });
+    match unicode {
+        Some(text) => (text, NameSource::UnicodeExtra),
+        None if flags & UTF8_FLAG != 0 => (decode_text(raw, flags, legacy), NameSource::Utf8),
+        None => (decode_text(raw, flags, legacy), NameSource::Legacy),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::unicode_extra;
+
+    #[test]
+    fn test_decode_text() {
+        assert_eq!(decode_text(b"\x81ber.txt", 0, &Cp437), "über.txt");
+        assert_eq!(decode_text(b"\xe2\x94\x80\xff", 0, &Cp437), "ΓöÇ\u{a0}");
+        assert_eq!(
+            decode_text("über.txt".as_bytes(), UTF8_FLAG, &Cp437),
+            "über.txt"
+        );
+
+        let latin1 = |bytes: &[u8]| bytes.iter().map(|&b| b as char).collect::<String>();
+        assert_eq!(decode_text(b"\xfcber.txt", 0, &latin1), "über.txt");
+    }
+
+    #[test]
+    fn test_unicode_extra() {
+        let raw = b"??.txt";
+        let extra = unicode_extra(0x7075, raw, "日本.txt");
+        assert_eq!(
+            decode_with_extra(raw, 0, &extra, 0x7075, &Cp437),
+            ("日本.txt".to_string(), NameSource::UnicodeExtra)
+        );
+
+        let stale = unicode_extra(0x7075, b"renamed.txt", "日本.txt");
+        assert_eq!(
+            decode_with_extra(raw, 0, &stale, 0x7075, &Cp437),
+            ("??.txt".to_string(), NameSource::Legacy)
+        );
+        assert_eq!(
+            decode_with_extra(raw, UTF8_FLAG, &[], 0x7075, &Cp437),
+            ("??.txt".to_string(), NameSource::Utf8)
+        );
+    }
+}
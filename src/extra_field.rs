@@ -0,0 +1,447 @@
+/*
+   Zip file reader and writer, in pure Rust.
+   Copyright (C) 2022 Matheus Xavier <mxavier@neonimp.com>
+
+   This program is free software: you can redistribute it and/or modify
+   it under the terms of the GNU Lesser General Public License as published by
+   the Free Software Foundation, either version 3 of the License, or
+   (at your option) any later version.
+
+   This program is distributed in the hope that it will be useful,
+   but WITHOUT ANY WARRANTY; without even the implied warranty of
+   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+   GNU General Public License for more details.
+
+   You should have received a copy of the GNU Lesser General Public License
+   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Typed parsing of the extra fields of the local and central headers.
+//!
+//! The extra field blob is a sequence of records, each a u16 header id, a u16
+//! length and that many bytes of data. Parsing is lossless: a record is only
+//! given a typed variant if writing it back gives the exact same bytes,
+//! otherwise it's kept as [`ExtraField::Unknown`], so
+//! [`write_extra_fields`] always reproduces the original blob.
+
+use crate::{UNICODE_COMMENT_EXTRA_ID, UNICODE_PATH_EXTRA_ID, ZIP64_EXTRA_ID};
+
+/// Header id of the NTFS extra field.
+pub const NTFS_EXTRA_ID: u16 = 0x000a;
+/// Header id of the extended timestamp extra field.
+pub const EXTENDED_TIMESTAMP_EXTRA_ID: u16 = 0x5455;
+/// Header id of the Info-ZIP Unix extra field, version 3.
+pub const INFO_ZIP_UNIX_EXTRA_ID: u16 = 0x7875;
+/// Header id of the WinZip AES encryption extra field.
+pub const AES_EXTRA_ID: u16 = 0x9901;
+
+/// A single record of an extra field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExtraField {
+    /// Zip64 extended information. It only holds the header values that
+    /// overflowed, in the order uncompressed size, compressed size and local
+    /// header offset, so what each value means depends on the header.
+    Zip64 {
+        values: Vec<u64>,
+        /// The disk the entry starts on, if that overflowed too.
+        disk_number: Option<u32>,
+    },
+    /// NTFS times, in 100 ns intervals since 1601-01-01 UTC.
+    Ntfs { mtime: u64, atime: u64, ctime: u64 },
+    /// Unix times in seconds since the epoch, `flags` tells which are set in
+    /// the local header, the central header only ever holds the mtime.
+    ExtendedTimestamp {
+        flags: u8,
+        mtime: Option<i32>,
+        atime: Option<i32>,
+        ctime: Option<i32>,
+    },
+    /// Unix owner of the file.
+    InfoZipUnix { version: u8, uid: u32, gid: u32 },
+    /// UTF-8 name, valid as long as the CRC-32 of the header name matches.
+    UnicodePath {
+        version: u8,
+        crc32: u32,
+        name: String,
+    },
+    /// UTF-8 comment, valid as long as the CRC-32 of the header comment matches.
+    UnicodeComment {
+        version: u8,
+        crc32: u32,
+        comment: String,
+    },
+    /// WinZip AES encryption, the real compression method is stored here.
+    Aes {
+        version: u16,
+        vendor_id: [u8; 2],
+        strength: u8,
+        compression: u16,
+    },
+    /// A record with an id we don't know or couldn't parse.
+    Unknown { id: u16, data: Vec<u8> },
+    /// Trailing bytes too short to be a record, like the padding added by
+    /// alignment tools.
+    Padding(Vec<u8>),
+}
+
+impl ExtraField {
+    /// Parse the data of a record with the given header `id`.
+    pub fn parse(id: u16, data: &[u8]) -> Self {
+        let typed = match id {
+            ZIP64_EXTRA_ID => parse_zip64(data),
+            NTFS_EXTRA_ID => parse_ntfs(data),
+            EXTENDED_TIMESTAMP_EXTRA_ID => parse_extended_timestamp(data),
+            INFO_ZIP_UNIX_EXTRA_ID => parse_info_zip_unix(data),
+            UNICODE_PATH_EXTRA_ID => {
+                parse_unicode(data).map(|(version, crc32, name)| ExtraField::UnicodePath {
+                    version,
+                    crc32,
+                    name,
+                })
+            }
+            UNICODE_COMMENT_EXTRA_ID => {
+                parse_unicode(data).map(|(version, crc32, comment)| ExtraField::UnicodeComment {
+                    version,
+                    crc32,
+                    comment,
+                })
+            }
+            AES_EXTRA_ID => parse_aes(data),
+            _ => None,
+        };
+
+        match typed {
+            Some(field) if field.data() == data => field,
+            _ => ExtraField::Unknown {
+                id,
+                data: data.to_vec(),
+            },
+        }
+    }
+
+    /// The header id of the record, `None` for padding.
+    pub fn id(&self) -> Option<u16> {
+        Some(match self {
+            ExtraField::Zip64 { .. } => ZIP64_EXTRA_ID,
+            ExtraField::Ntfs { .. } => NTFS_EXTRA_ID,
+            ExtraField::ExtendedTimestamp { .. } => EXTENDED_TIMESTAMP_EXTRA_ID,
+            ExtraField::InfoZipUnix { .. } => INFO_ZIP_UNIX_EXTRA_ID,
+            ExtraField::UnicodePath { .. } => UNICODE_PATH_EXTRA_ID,
+            ExtraField::UnicodeComment { .. } => UNICODE_COMMENT_EXTRA_ID,
+            ExtraField::Aes { .. } => AES_EXTRA_ID,
+            ExtraField::Unknown { id, .. } => *id,
+            ExtraField::Padding(_) => return None,
+        })
+    }
+
+    /// Serialize the data of the record, without the id and length.
+    pub fn data(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        match self {
+            ExtraField::Zip64 {
+                values,
+                disk_number,
+            } => {
+                for value in values {
+                    out.extend_from_slice(&value.to_le_bytes());
+                }
+                if let Some(disk) = disk_number {
+                    out.extend_from_slice(&disk.to_le_bytes());
+                }
+            }
+            ExtraField::Ntfs {
+                mtime,
+                atime,
+                ctime,
+            } => {
+                out.extend_from_slice(&0u32.to_le_bytes());
+                out.extend_from_slice(&1u16.to_le_bytes());
+                out.extend_from_slice(&24u16.to_le_bytes());
+                for time in [mtime, atime, ctime] {
+                    out.extend_from_slice(&time.to_le_bytes());
+                }
+            }
+            ExtraField::ExtendedTimestamp {
+                flags,
+                mtime,
+                atime,
+                ctime,
+            } => {
+                out.push(*flags);
+                for time in [mtime, atime, ctime].into_iter().flatten() {
+                    out.extend_from_slice(&time.to_le_bytes());
+                }
+            }
+            ExtraField::InfoZipUnix { version, uid, gid } => {
+                out.push(*version);
+                for id in [uid, gid] {
+                    out.push(4);
+                    out.extend_from_slice(&id.to_le_bytes());
+                }
+            }
+            ExtraField::UnicodePath {
+                version,
+                crc32,
+                name: text,
+            }
+            | ExtraField::UnicodeComment {
+                version,
+                crc32,
+                comment: text,
+            } => {
+                out.push(*version);
+                out.extend_from_slice(&crc32.to_le_bytes());
+                out.extend_from_slice(text.as_bytes());
+            }
+            ExtraField::Aes {
+                version,
+                vendor_id,
+                strength,
+                compression,
+            } => {
+                out.extend_from_slice(&version.to_le_bytes());
+                out.extend_from_slice(vendor_id);
+                out.push(*strength);
+                out.extend_from_slice(&compression.to_le_bytes());
+            }
+            ExtraField::Unknown { data, .. } | ExtraField::Padding(data) => {
+                out.extend_from_slice(data);
+            }
+        }
+        out
+    }
+
+    /// Serialize the whole record, id and length included.
+    pub fn write(&self, out: &mut Vec<u8>) {
+        let data = self.data();
+        if let Some(id) = self.id() {
+            out.extend_from_slice(&id.to_le_bytes());
+            out.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        }
+        out.extend_from_slice(&data);
+    }
+}
+
+fn le_u16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(at..at + 2)?.try_into().unwrap(),
+    ))
+}
+
+fn le_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(at..at + 4)?.try_into().unwrap(),
+    ))
+}
+
+fn le_u64(data: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(at..at + 8)?.try_into().unwrap(),
+    ))
+}
+
+fn parse_zip64(data: &[u8]) -> Option<ExtraField> {
+    let values_len = data.len() - data.len() % 8;
+    let disk_number = match data.len() % 8 {
+        0 => None,
+        4 => Some(le_u32(data, values_len)?),
+        _ => return None,
+    };
+    Some(ExtraField::Zip64 {
+        values: data[..values_len]
+            .chunks_exact(8)
+            .map(|value| u64::from_le_bytes(value.try_into().unwrap()))
+            .collect(),
+        disk_number,
+    })
+}
+
+/// Only the usual layout is understood, a reserved u32 and a single times
+/// attribute, anything else is kept as unknown.
+fn parse_ntfs(data: &[u8]) -> Option<ExtraField> {
+    if le_u16(data, 4)? != 1 || le_u16(data, 6)? != 24 {
+        return None;
+    }
+    Some(ExtraField::Ntfs {
+        mtime: le_u64(data, 8)?,
+        atime: le_u64(data, 16)?,
+        ctime: le_u64(data, 24)?,
+    })
+}
+
+fn parse_extended_timestamp(data: &[u8]) -> Option<ExtraField> {
+    let flags = *data.first()?;
+    let mut at = 1;
+    let mut time = |bit: u8| {
+        if flags & bit == 0 {
+            return None;
+        }
+        let time = le_u32(data, at)? as i32;
+        at += 4;
+        Some(time)
+    };
+    Some(ExtraField::ExtendedTimestamp {
+        flags,
+        mtime: time(1),
+        atime: time(2),
+        ctime: time(4),
+    })
+}
+
+fn parse_info_zip_unix(data: &[u8]) -> Option<ExtraField> {
+    let version = *data.first()?;
+    let uid_len = *data.get(1)? as usize;
+    let uid = data.get(2..2 + uid_len)?;
+    let gid_len = *data.get(2 + uid_len)? as usize;
+    let gid = data.get(3 + uid_len..3 + uid_len + gid_len)?;
+    Some(ExtraField::InfoZipUnix {
+        version,
+        uid: le_u32(uid, 0)?,
+        gid: le_u32(gid, 0)?,
+    })
+}
+
+fn parse_unicode(data: &[u8]) -> Option<(u8, u32, String)> {
+    let version = *data.first()?;
+    let crc32 = le_u32(data, 1)?;
+    let text = String::from_utf8(data[5..].to_vec()).ok()?;
+    Some((version, crc32, text))
+}
+
+fn parse_aes(data: &[u8]) -> Option<ExtraField> {
+    Some(ExtraField::Aes {
+        version: le_u16(data, 0)?,
+        vendor_id: data.get(2..4)?.try_into().unwrap(),
+        strength: *data.get(4)?,
+        compression: le_u16(data, 5)?,
+    })
+}
+
+/// Iterator over the records of an extra field blob, see
+/// [`crate::structures::CentralDirectory::extra_fields`].
+#[derive(Debug, Clone)]
+pub struct ExtraFields<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> ExtraFields<'a> {
+    pub fn new(extra: &'a [u8]) -> Self {
+        ExtraFields { rest: extra }
+    }
+}
+
+impl Iterator for ExtraFields<'_> {
+    type Item = ExtraField;
+
+    fn next(&mut self) -> Option<ExtraField> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        let record = le_u16(self.rest, 0).zip(le_u16(self.rest, 2));
+        match record {
+            Some((id, len)) if self.rest.len() >= 4 + len as usize => {
+                let (data, rest) = self.rest[4..].split_at(len as usize);
+                self.rest = rest;
+                Some(ExtraField::parse(id, data))
+            }
+            _ => {
+                let padding = std::mem::take(&mut self.rest);
+                Some(ExtraField::Padding(padding.to_vec()))
+            }
+        }
+    }
+}
+
+/// Serialize `fields` back into an extra field blob.
+pub fn write_extra_fields<'a>(fields: impl IntoIterator<Item = &'a ExtraField>) -> Vec<u8> {
+    let mut out = Vec::new();
+    for field in fields {
+        field.write(&mut out);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::unicode_extra;
+
+    fn record(id: u16, data: &[u8]) -> Vec<u8> {
+        let mut out = id.to_le_bytes().to_vec();
+        out.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        out.extend_from_slice(data);
+        out
+    }
+
+    #[test]
+    fn test_parse_extra_fields() {
+        let extra = [
+            record(ZIP64_EXTRA_ID, &[1, 0, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0]),
+            record(EXTENDED_TIMESTAMP_EXTRA_ID, &[3, 1, 0, 0, 0, 2, 0, 0, 0]),
+            record(
+                INFO_ZIP_UNIX_EXTRA_ID,
+                &[1, 4, 232, 3, 0, 0, 4, 100, 0, 0, 0],
+            ),
+            unicode_extra(UNICODE_PATH_EXTRA_ID, b"a", "ä"),
+            record(AES_EXTRA_ID, &[2, 0, b'A', b'E', 3, 8, 0]),
+            record(0xcafe, b"opaque"),
+            vec![0, 0],
+        ]
+        .concat();
+        let fields = ExtraFields::new(&extra).collect::<Vec<_>>();
+        assert_eq!(
+            fields,
+            [
+                ExtraField::Zip64 {
+                    values: vec![1],
+                    disk_number: Some(7),
+                },
+                ExtraField::ExtendedTimestamp {
+                    flags: 3,
+                    mtime: Some(1),
+                    atime: Some(2),
+                    ctime: None,
+                },
+                ExtraField::InfoZipUnix {
+                    version: 1,
+                    uid: 1000,
+                    gid: 100,
+                },
+                ExtraField::UnicodePath {
+                    version: 1,
+                    crc32: 0xE8B7BE43,
+                    name: "ä".to_string(),
+                },
+                ExtraField::Aes {
+                    version: 2,
+                    vendor_id: *b"AE",
+                    strength: 3,
+                    compression: 8,
+                },
+                ExtraField::Unknown {
+                    id: 0xcafe,
+                    data: b"opaque".to_vec(),
+                },
+                ExtraField::Padding(vec![0, 0]),
+            ]
+        );
+        assert_eq!(write_extra_fields(&fields), extra);
+    }
+
+    #[test]
+    fn test_unusual_layouts_stay_unknown() {
+        // 2 byte uid and gid, an NTFS attribute other than the times, and a
+        // Unicode Path field that isn't UTF-8.
+        let extra = [
+            record(INFO_ZIP_UNIX_EXTRA_ID, &[1, 2, 232, 3, 2, 100, 0]),
+            record(NTFS_EXTRA_ID, &[0, 0, 0, 0, 2, 0, 4, 0, 1, 2, 3, 4]),
+            record(UNICODE_PATH_EXTRA_ID, &[1, 0, 0, 0, 0, 0xff]),
+        ]
+        .concat();
+        let fields = ExtraFields::new(&extra).collect::<Vec<_>>();
+        assert!(fields
+            .iter()
+            .all(|field| matches!(field, ExtraField::Unknown { .. })));
+        assert_eq!(write_extra_fields(&fields), extra);
+    }
+}
@@ -56,7 +56,10 @@ pub struct IZipEntry {
 /// # Safety
 /// This function presents an interface to C code, but is using the safe internal API.
 #[no_mangle]
-pub unsafe extern "C" fn zip_open_buffer(buf: &mut c_uchar, buf_len: size_t) -> *mut IZipReader {
+pub unsafe extern "C" fn zip_open_buffer(
+    buf: &mut c_uchar,
+    buf_len: size_t,
+) -> *mut IZipReader<'_> {
     // Convert the buffer to a slice
     let buf = std::slice::from_raw_parts_mut(buf, buf_len);
     // Make a SeekableCursor from the buffer
@@ -132,7 +135,6 @@ pub unsafe extern "C" fn zip_close(reader: *mut IZipReader) {
 /// # Safety
 /// This function presents an interface to C code, but is using the safe internal API.
 #[no_mangle]
-#[inline]
 pub unsafe extern "C" fn zip_get_error(reader: *const IZipReader) -> *const ZipError {
     let reader = if !reader.is_null() {
         &*reader
@@ -159,7 +161,6 @@ pub unsafe extern "C" fn zip_get_error(reader: *const IZipReader) -> *const ZipE
 /// # Safety
 /// This function presents an interface to C code, but is using the safe internal API.
 #[no_mangle]
-#[inline]
 pub unsafe extern "C" fn zip_error_get_message(
     error: *const ZipError,
     out_buf: *mut c_char,
@@ -0,0 +1,306 @@
+/*
+   Zip file reader and writer, in pure Rust.
+   Copyright (C) 2022 Matheus Xavier <mxavier@neonimp.com>
+
+   This program is free software: you can redistribute it and/or modify
+   it under the terms of the GNU Lesser General Public License as published by
+   the Free Software Foundation, either version 3 of the License, or
+   (at your option) any later version.
+
+   This program is distributed in the hope that it will be useful,
+   but WITHOUT ANY WARRANTY; without even the implied warranty of
+   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+   GNU General Public License for more details.
+
+   You should have received a copy of the GNU Lesser General Public License
+   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Path matchers, to look up entries in a [`crate::index::ZipIndex`] and to
+//! pick the entries to extract.
+
+use std::path::{Component, Path, PathBuf};
+
+use crate::structures::CentralDirectory;
+use crate::{Result, ZipError};
+
+/// Decides which entries are extracted, see
+/// [`crate::reader::ZipReader::extract_matching`].
+pub trait EntryFilter {
+    fn matches(&self, path: &Path, entry: &CentralDirectory) -> bool;
+}
+
+impl<F: Fn(&Path, &CentralDirectory) -> bool> EntryFilter for F {
+    fn matches(&self, path: &Path, entry: &CentralDirectory) -> bool {
+        self(path, entry)
+    }
+}
+
+/// Matches a path and everything under it, comparing whole components, so
+/// `META-INF` matches `META-INF/MANIFEST.MF` but not `META-INF.bak`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathPrefix(pub PathBuf);
+
+impl PathPrefix {
+    pub fn new<P: AsRef<Path>>(prefix: P) -> Self {
+        PathPrefix(prefix.as_ref().to_path_buf())
+    }
+
+    pub fn is_match(&self, path: &Path) -> bool {
+        path.starts_with(&self.0)
+    }
+}
+
+impl EntryFilter for PathPrefix {
+    fn matches(&self, path: &Path, _: &CentralDirectory) -> bool {
+        self.is_match(path)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Literal(char),
+    /// `?`, any single character.
+    Any,
+    /// `*`, any run of characters within a component.
+    Star,
+    /// `[...]` or `[!...]`, a character in or out of the inclusive ranges.
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    /// `**`, any number of components, none included.
+    Recursive,
+    /// A single component.
+    Component(Vec<Token>),
+}
+
+/// A shell-style glob pattern matched against whole paths, with `/` between
+/// components.
+///
+/// `*` matches any run of characters inside a component, `**` as a whole
+/// component matches any number of components, `?` matches one character,
+/// `[abc]` and `[a-z]` match one character of the class and `[!abc]` or
+/// `[^abc]` one character out of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Glob {
+    pattern: String,
+    segments: Vec<Segment>,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Result<Self> {
+        let segments = pattern
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| match segment {
+                "**" => Ok(Segment::Recursive),
+                _ => parse_component(pattern, segment).map(Segment::Component),
+            })
+            .collect::<Result<_>>()?;
+        Ok(Glob {
+            pattern: pattern.to_string(),
+            segments,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn is_match(&self, path: &Path) -> bool {
+        let components = path
+            .components()
+            .filter(|component| !matches!(component, Component::RootDir))
+            .map(|component| component.as_os_str().to_string_lossy().chars().collect())
+            .collect::<Vec<Vec<char>>>();
+        match_segments(&self.segments, &components)
+    }
+
+    /// The leading components without any wildcard, every match is under
+    /// this path, which allows range scans over sorted paths.
+    pub fn literal_prefix(&self) -> PathBuf {
+        self.segments
+            .iter()
+            .map_while(|segment| match segment {
+                Segment::Component(tokens) => tokens
+                    .iter()
+                    .map(|token| match token {
+                        Token::Literal(c) => Some(*c),
+                        _ => None,
+                    })
+                    .collect::<Option<String>>(),
+                Segment::Recursive => None,
+            })
+            .collect()
+    }
+}
+
+impl EntryFilter for Glob {
+    fn matches(&self, path: &Path, _: &CentralDirectory) -> bool {
+        self.is_match(path)
+    }
+}
+
+fn parse_component(pattern: &str, segment: &str) -> Result<Vec<Token>> {
+    let invalid = |reason: &str| ZipError::InvalidPattern(pattern.to_string(), reason.to_string());
+    let mut tokens = Vec::new();
+    let mut chars = segment.chars().peekable();
+    while let Some(c) = chars.next() {
+        tokens.push(match c {
+            '?' => Token::Any,
+            '*' => {
+                // Repeated stars inside a component are the same as one.
+                while chars.next_if_eq(&'*').is_some() {}
+                Token::Star
+            }
+            '[' => {
+                let negated = chars.next_if(|&c| c == '!' || c == '^').is_some();
+                let mut ranges = Vec::new();
+                let mut first = true;
+                loop {
+                    let start = match chars.next() {
+                        Some(']') if !first => break,
+                        Some(c) => c,
+                        None => return Err(invalid("unclosed character class")),
+                    };
+                    first = false;
+                    let mut ahead = chars.clone();
+                    let end = match (ahead.next(), ahead.next()) {
+                        (Some('-'), Some(end)) if end != ']' => {
+                            chars.nth(1);
+                            end
+                        }
+                        _ => start,
+                    };
+                    if end < start {
+                        return Err(invalid("character range out of order"));
+                    }
+                    ranges.push((start, end));
+                }
+                Token::Class { negated, ranges }
+            }
+            c => Token::Literal(c),
+        });
+    }
+    Ok(tokens)
+}
+
+fn match_segments(segments: &[Segment], components: &[Vec<char>]) -> bool {
+    wildcard_match(
+        segments,
+        components,
+        |segment| *segment == Segment::Recursive,
+        |segment, component| match segment {
+            Segment::Component(tokens) => match_tokens(tokens, component),
+            Segment::Recursive => unreachable!(),
+        },
+    )
+}
+
+fn match_tokens(tokens: &[Token], chars: &[char]) -> bool {
+    wildcard_match(
+        tokens,
+        chars,
+        |token| *token == Token::Star,
+        |token, c| match token {
+            Token::Literal(literal) => literal == c,
+            Token::Any => true,
+            Token::Class { negated, ranges } => {
+                ranges.iter().any(|(start, end)| (start..=end).contains(&c)) != *negated
+            }
+            Token::Star => unreachable!(),
+        },
+    )
+}
+
+/// Match `items` against `pattern`, where the stars match any run of items
+/// and every other element of the pattern matches exactly one item.
+///
+/// Only the last star is ever backtracked to: once the pattern after a later
+/// star matches, moving an earlier star can't help. This keeps the matching
+/// in `O(pattern * items)` instead of exponential in the number of stars.
+fn wildcard_match<P, I>(
+    pattern: &[P],
+    items: &[I],
+    is_star: impl Fn(&P) -> bool,
+    matches: impl Fn(&P, &I) -> bool,
+) -> bool {
+    let (mut p, mut i) = (0, 0);
+    // The pattern position after the last star, and the item it resumes at.
+    let mut backtrack = None;
+    while i < items.len() {
+        match pattern.get(p) {
+            Some(element) if is_star(element) => {
+                p += 1;
+                backtrack = Some((p, i));
+            }
+            Some(element) if matches(element, &items[i]) => {
+                p += 1;
+                i += 1;
+            }
+            // Let the last star swallow one more item and try again.
+            _ => match backtrack {
+                Some((after_star, resume)) => {
+                    p = after_star;
+                    i = resume + 1;
+                    backtrack = Some((after_star, i));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(is_star)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_match(pattern: &str, path: &str) -> bool {
+        Glob::new(pattern).unwrap().is_match(Path::new(path))
+    }
+
+    #[test]
+    fn test_glob() {
+        assert!(is_match("assets/**/*.png", "assets/a.png"));
+        assert!(is_match("assets/**/*.png", "assets/ui/icons/a.png"));
+        assert!(!is_match("assets/**/*.png", "assets/a.png.txt"));
+        assert!(!is_match("assets/*.png", "assets/ui/a.png"));
+        assert!(is_match("**", "anything/at/all"));
+        assert!(is_match("file?.txt", "file1.txt"));
+        assert!(!is_match("file?.txt", "file10.txt"));
+        assert!(is_match("file[0-9].txt", "file7.txt"));
+        assert!(!is_match("file[!0-9].txt", "file7.txt"));
+        assert!(is_match("file[]x].txt", "file].txt"));
+        assert!(!is_match("dir/*", "dir/"));
+        assert!(is_match("dir", "dir/"));
+        assert!(is_match("*a*b*", "xaybz"));
+        assert!(is_match("a/**/b/**/c", "a/b/x/b/c"));
+        assert!(!is_match("a/**/b/**/c", "a/b/x/c/d"));
+
+        // Many stars don't make the matching blow up.
+        let long = "a".repeat(80);
+        assert!(!is_match("*a*a*a*a*a*a*a*a*a*a*b", &long));
+        assert!(is_match("*a*a*a*a*a*a*a*a*a*a", &long));
+        let deep = vec!["a"; 80].join("/");
+        assert!(!is_match("**/a/**/a/**/a/**/a/**/a/**/b", &deep));
+
+        let glob = Glob::new("assets/ui/**/*.png").unwrap();
+        assert_eq!(glob.literal_prefix(), Path::new("assets/ui"));
+        assert_eq!(Glob::new("*.txt").unwrap().literal_prefix(), Path::new(""));
+    }
+
+    #[test]
+    fn test_invalid_glob() {
+        assert_eq!(
+            Glob::new("a/[bc").unwrap_err(),
+            ZipError::InvalidPattern("a/[bc".to_string(), "unclosed character class".to_string())
+        );
+        assert!(Glob::new("[z-a]").is_err());
+    }
+}
@@ -0,0 +1,609 @@
+/*
+   Zip file reader and writer, in pure Rust.
+   Copyright (C) 2022 Matheus Xavier <mxavier@neonimp.com>
+
+   This program is free software: you can redistribute it and/or modify
+   it under the terms of the GNU Lesser General Public License as published by
+   the Free Software Foundation, either version 3 of the License, or
+   (at your option) any later version.
+
+   This program is distributed in the hope that it will be useful,
+   but WITHOUT ANY WARRANTY; without even the implied warranty of
+   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+   GNU General Public License for more details.
+
+   You should have received a copy of the GNU Lesser General Public License
+   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! The index of the entries of an archive, built from its central directory.
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+
+use unicode_normalization::UnicodeNormalization;
+
+use crate::glob::Glob;
+use crate::structures::CentralDirectory;
+use crate::{Result, ZipError};
+
+/// Which record a lookup returns when several have the same name.
+///
+/// Tools disagree on this, which attackers use to show one file to a scanner
+/// and extract another, so [`ZipIndex::duplicates`] should be checked when
+/// that matters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatePolicy {
+    /// The first record in the central directory wins.
+    FirstWins,
+    /// The last record in the central directory wins, like most extractors
+    /// that overwrite the earlier files.
+    #[default]
+    LastWins,
+}
+
+/// How the paths given to [`ZipIndex::find`] are matched against the names
+/// of the entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LookupMode {
+    /// The path must be the name of the entry.
+    #[default]
+    Exact,
+    /// Case and Unicode normalization are ignored, so `Readme.TXT` finds
+    /// `README.txt` and the NFD names written on macOS are found with NFC
+    /// paths. A path matching several names is an error.
+    Insensitive,
+}
+
+/// A file or directory listed by [`ZipIndex::read_dir`] or [`ZipIndex::walk`].
+#[derive(Debug, Clone, Copy)]
+pub struct DirEntry<'a> {
+    pub path: &'a Path,
+    /// The record of the entry, `None` for directories that are only implied
+    /// by the paths of other entries.
+    pub entry: Option<&'a CentralDirectory>,
+    pub is_dir: bool,
+}
+
+pub(crate) type Names = BTreeMap<PathBuf, Vec<usize>>;
+pub(crate) type Tree = BTreeMap<PathBuf, Vec<PathBuf>>;
+
+/// Every central directory record of an archive, looked up by name.
+///
+/// All records are kept in archive order, even the ones sharing a name, the
+/// lookups by name return one of them according to the [`DuplicatePolicy`].
+///
+/// The index also holds the directory tree of the archive, including the
+/// directories that have no record of their own but contain other entries.
+/// The root of the tree is the empty path.
+pub struct ZipIndex {
+    entries: Vec<CentralDirectory>,
+    /// Positions in `entries` of the records with each name, in archive order.
+    names: Names,
+    /// The children of every directory, sorted.
+    tree: Tree,
+    policy: DuplicatePolicy,
+    /// The names under their folded key, sorted, only built in the
+    /// [`LookupMode::Insensitive`] mode.
+    folded: Option<BTreeMap<String, Vec<PathBuf>>>,
+}
+
+impl ZipIndex {
+    /// Index `entries`, which are expected in archive order.
+    pub fn new(entries: Vec<CentralDirectory>) -> Self {
+        let mut index = ZipIndex {
+            entries: Vec::with_capacity(entries.len()),
+            names: BTreeMap::new(),
+            tree: BTreeMap::from([(PathBuf::new(), Vec::new())]),
+            policy: DuplicatePolicy::default(),
+            folded: None,
+        };
+        for entry in entries {
+            index.push(entry.filename.clone(), entry);
+        }
+        index
+    }
+
+    /// Rebuild an index from the parts returned by [`ZipIndex::parts`],
+    /// without sorting or inserting anything again. The parts don't include
+    /// the duplicate policy or lookup mode, the index starts with the
+    /// defaults.
+    pub(crate) fn from_parts(entries: Vec<CentralDirectory>, names: Names, tree: Tree) -> Self {
+        ZipIndex {
+            entries,
+            names,
+            tree,
+            policy: DuplicatePolicy::default(),
+            folded: None,
+        }
+    }
+
+    /// The records, the positions of the records of every name and the
+    /// directory tree.
+    pub(crate) fn parts(&self) -> (&[CentralDirectory], &Names, &Tree) {
+        (&self.entries, &self.names, &self.tree)
+    }
+
+    pub fn duplicate_policy(&self) -> DuplicatePolicy {
+        self.policy
+    }
+
+    /// Choose which record the lookups return for duplicate names.
+    pub fn set_duplicate_policy(&mut self, policy: DuplicatePolicy) {
+        self.policy = policy;
+    }
+
+    pub fn lookup_mode(&self) -> LookupMode {
+        match self.folded {
+            Some(_) => LookupMode::Insensitive,
+            None => LookupMode::Exact,
+        }
+    }
+
+    /// Choose how [`ZipIndex::find`] matches paths, the secondary index the
+    /// insensitive mode needs is built here.
+    pub fn set_lookup_mode(&mut self, mode: LookupMode) {
+        match mode {
+            LookupMode::Exact => self.folded = None,
+            LookupMode::Insensitive if self.folded.is_none() => {
+                let mut folded = BTreeMap::new();
+                for path in self.names.keys() {
+                    add_folded(&mut folded, path);
+                }
+                self.folded = Some(folded);
+            }
+            LookupMode::Insensitive => {}
+        }
+    }
+
+    /// The position the policy picks out of `positions`.
+    fn position(&self, positions: &[usize]) -> usize {
+        match self.policy {
+            DuplicatePolicy::FirstWins => positions[0],
+            DuplicatePolicy::LastWins => positions[positions.len() - 1],
+        }
+    }
+
+    fn pick(&self, positions: &[usize]) -> &CentralDirectory {
+        &self.entries[self.position(positions)]
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &CentralDirectory> {
+        self.values().filter(|info| !info.is_directory)
+    }
+
+    pub fn dirs(&self) -> impl Iterator<Item = &CentralDirectory> {
+        self.values().filter(|info| info.is_directory)
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&CentralDirectory> {
+        self.names.get(path).map(|positions| self.pick(positions))
+    }
+
+    /// The name of the entry `path` refers to in the lookup mode, `None` if
+    /// there's none, or an [`ZipError::AmbiguousPath`] error if it could be
+    /// several entries.
+    pub fn resolve(&self, path: &Path) -> Result<Option<&Path>> {
+        let folded = match &self.folded {
+            Some(folded) => folded,
+            None => {
+                return Ok(self
+                    .names
+                    .get_key_value(path)
+                    .map(|(name, _)| name.as_path()))
+            }
+        };
+        match folded.get(&fold(path)).map(Vec::as_slice) {
+            None => Ok(None),
+            Some([name]) => Ok(Some(name)),
+            Some(names) => Err(ZipError::AmbiguousPath(path.into(), names.to_vec())),
+        }
+    }
+
+    /// Look up `path` in the lookup mode, unlike [`ZipIndex::get`] that
+    /// always matches exactly.
+    pub fn find(&self, path: &Path) -> Result<&CentralDirectory> {
+        self.resolve(path)?
+            .and_then(|name| self.get(name))
+            .ok_or_else(|| ZipError::EntryNotFound(path.into()))
+    }
+
+    /// The names that can't be told apart in the insensitive lookup mode,
+    /// grouped and sorted.
+    pub fn ambiguities(&self) -> impl Iterator<Item = Vec<&Path>> {
+        let mut groups = BTreeMap::<String, Vec<&Path>>::new();
+        for path in self.names.keys() {
+            groups.entry(fold(path)).or_default().push(path);
+        }
+        groups.into_values().filter(|names| names.len() > 1)
+    }
+
+    /// Every record named `path`, in archive order.
+    pub fn get_all(&self, path: &Path) -> impl Iterator<Item = &CentralDirectory> {
+        self.names
+            .get(path)
+            .into_iter()
+            .flatten()
+            .map(|&position| &self.entries[position])
+    }
+
+    /// The names that appear more than once, with all of their records in
+    /// archive order.
+    pub fn duplicates(&self) -> impl Iterator<Item = (&Path, Vec<&CentralDirectory>)> {
+        self.names
+            .iter()
+            .filter(|(_, positions)| positions.len() > 1)
+            .map(|(path, positions)| {
+                let records = positions.iter().map(|&pos| &self.entries[pos]);
+                (path.as_path(), records.collect())
+            })
+    }
+
+    /// Add a record under `path`, after any other with the same name. The
+    /// record that lookups returned for `path` before is returned if the new
+    /// one takes its place under the [`DuplicatePolicy`], it stays in
+    /// [`ZipIndex::entries`].
+    pub fn insert(&mut self, path: PathBuf, info: CentralDirectory) -> Option<CentralDirectory> {
+        let replaced = match self.policy {
+            DuplicatePolicy::FirstWins => None,
+            DuplicatePolicy::LastWins => self.names.get(&path).map(|p| self.pick(p).clone()),
+        };
+        self.push(path, info);
+        replaced
+    }
+
+    fn push(&mut self, path: PathBuf, info: CentralDirectory) {
+        if info.is_directory {
+            self.tree.entry(path.clone()).or_default();
+        }
+        self.add_to_tree(&path);
+        if let Some(folded) = &mut self.folded {
+            add_folded(folded, &path);
+        }
+        self.names.entry(path).or_default().push(self.entries.len());
+        self.entries.push(info);
+    }
+
+    /// Add `path` to the children of its parent, adding the parent to the
+    /// tree too if it's not there yet.
+    fn add_to_tree(&mut self, path: &Path) {
+        if path.as_os_str().is_empty() {
+            return;
+        }
+        let parent = path.parent().unwrap_or(Path::new(""));
+        let parent_known = self.tree.contains_key(parent);
+        let children = self.tree.entry(parent.to_path_buf()).or_default();
+        if let Err(at) = children.binary_search_by(|child| child.as_path().cmp(path)) {
+            children.insert(at, path.to_path_buf());
+        }
+        if !parent_known {
+            self.add_to_tree(parent);
+        }
+    }
+
+    /// The entries at `path` and under it, sorted by name, see
+    /// [`crate::glob::PathPrefix`] for the matching rules.
+    pub fn prefix<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> impl Iterator<Item = (&'a Path, &'a CentralDirectory)> + 'a {
+        // Paths sort component by component, so everything under `path`
+        // comes right after it.
+        self.names
+            .range::<Path, _>((Bound::Included(path), Bound::Unbounded))
+            .take_while(move |(name, _)| name.starts_with(path))
+            .map(|(name, positions)| (name.as_path(), self.pick(positions)))
+    }
+
+    /// The entries matching the glob `pattern`, sorted by name. Only the
+    /// entries under the literal leading components of the pattern are
+    /// looked at.
+    pub fn glob(
+        &self,
+        pattern: &str,
+    ) -> Result<impl Iterator<Item = (&Path, &CentralDirectory)> + '_> {
+        let glob = Glob::new(pattern)?;
+        let prefix = glob.literal_prefix();
+        let candidates = self
+            .names
+            .range::<Path, _>((Bound::Included(prefix.as_path()), Bound::Unbounded))
+            .take_while(move |(name, _)| name.starts_with(&prefix));
+        Ok(candidates
+            .filter(move |(name, _)| glob.is_match(name))
+            .map(|(name, positions)| (name.as_path(), self.pick(positions))))
+    }
+
+    /// Whether `path` is a directory, with a record or implied by the paths
+    /// of other entries.
+    pub fn is_dir(&self, path: &Path) -> bool {
+        self.tree.contains_key(path)
+    }
+
+    fn dir_entry<'a>(&'a self, path: &'a Path) -> DirEntry<'a> {
+        DirEntry {
+            path,
+            entry: self.get(path),
+            is_dir: self.is_dir(path),
+        }
+    }
+
+    /// The entries directly inside the directory `path`, sorted by name,
+    /// `None` if it's not a directory. Use the empty path for the root.
+    pub fn read_dir(&self, path: &Path) -> Option<impl Iterator<Item = DirEntry<'_>>> {
+        let children = self.tree.get(path)?;
+        Some(children.iter().map(|child| self.dir_entry(child)))
+    }
+
+    /// The directory containing `path`, the empty path for the entries at the
+    /// root, or `None` if `path` is not in the archive.
+    pub fn parent<'a>(&self, path: &'a Path) -> Option<&'a Path> {
+        if path.as_os_str().is_empty() || !(self.contains(path) || self.is_dir(path)) {
+            return None;
+        }
+        Some(path.parent().unwrap_or(Path::new("")))
+    }
+
+    /// Every file and directory of the tree, depth first with each directory
+    /// right before its contents, sorted by name within a directory.
+    pub fn walk(&self) -> impl Iterator<Item = DirEntry<'_>> {
+        let mut stack = self.tree[Path::new("")].iter().rev().collect::<Vec<_>>();
+        std::iter::from_fn(move || {
+            let path = stack.pop()?;
+            if let Some(children) = self.tree.get(path) {
+                stack.extend(children.iter().rev());
+            }
+            Some(self.dir_entry(path))
+        })
+    }
+
+    /// Every directory, including the ones without a record, sorted by name.
+    pub fn directories(&self) -> impl Iterator<Item = &Path> {
+        self.tree
+            .keys()
+            .map(|path| path.as_path())
+            .filter(|path| !path.as_os_str().is_empty())
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.names.contains_key(path)
+    }
+
+    /// The number of distinct names, see [`ZipIndex::entries`] for the
+    /// number of records.
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Every record, duplicates included, in archive order.
+    pub fn entries(&self) -> &[CentralDirectory] {
+        &self.entries
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Path, &CentralDirectory)> {
+        self.names
+            .iter()
+            .map(|(path, positions)| (path.as_path(), self.pick(positions)))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Path> {
+        self.names.keys().map(|path| path.as_path())
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &CentralDirectory> {
+        self.names.values().map(|positions| self.pick(positions))
+    }
+
+    pub fn into_keys(self) -> impl Iterator<Item = PathBuf> {
+        self.names.into_keys()
+    }
+
+    pub fn into_values(self) -> impl Iterator<Item = CentralDirectory> {
+        self.into_iter().map(|(_, info)| info)
+    }
+
+    /// Every record, duplicates included, in archive order.
+    pub fn into_entries(self) -> Vec<CentralDirectory> {
+        self.entries
+    }
+}
+
+/// The key of `path` in the insensitive lookup mode, lowercase and in NFC
+/// form, with `/` between the components.
+fn fold(path: &Path) -> String {
+    let name = path
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    name.nfd()
+        .collect::<String>()
+        .to_lowercase()
+        .nfc()
+        .collect()
+}
+
+fn add_folded(folded: &mut BTreeMap<String, Vec<PathBuf>>, path: &Path) {
+    let names = folded.entry(fold(path)).or_default();
+    if let Err(at) = names.binary_search_by(|name| name.as_path().cmp(path)) {
+        names.insert(at, path.to_path_buf());
+    }
+}
+
+impl IntoIterator for ZipIndex {
+    type Item = (PathBuf, CentralDirectory);
+    type IntoIter = std::vec::IntoIter<(PathBuf, CentralDirectory)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let picked = self
+            .names
+            .values()
+            .map(|positions| self.position(positions))
+            .collect::<Vec<_>>();
+        let mut entries = self.entries.into_iter().map(Some).collect::<Vec<_>>();
+        self.names
+            .into_keys()
+            .zip(picked)
+            .map(|(path, position)| (path, entries[position].take().unwrap()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::UTF8_FLAG;
+    use crate::reader::ZipReader;
+    use crate::test_util::{TestArchive, TestEntry};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_duplicate_names() {
+        let data = TestArchive::new()
+            .add("a.txt", b"first")
+            .add("b.txt", b"other")
+            .add("a.txt", b"second")
+            .finish();
+        let mut zip = ZipReader::new(Cursor::new(data)).unwrap();
+        assert_eq!(zip.index().len(), 2);
+        assert_eq!(zip.index().entries().len(), 3);
+        assert_eq!(zip.dump_file(&"a.txt").unwrap(), b"second");
+
+        let duplicates = zip.index().duplicates().collect::<Vec<_>>();
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].0, Path::new("a.txt"));
+        let sizes = duplicates[0].1.iter().map(|cd| cd.uncompressed_size);
+        assert_eq!(sizes.collect::<Vec<_>>(), [5, 6]);
+
+        zip.set_duplicate_policy(DuplicatePolicy::FirstWins);
+        assert_eq!(zip.dump_file(&"a.txt").unwrap(), b"first");
+        let values = zip.index().values().map(|cd| cd.uncompressed_size);
+        assert_eq!(values.collect::<Vec<_>>(), [5, 5]);
+
+        // Inserting returns the record the new one hides, if any.
+        let mut index = ZipIndex::new(zip.index().entries().to_vec());
+        let mut record = index.get(Path::new("b.txt")).unwrap().clone();
+        record.uncompressed_size = 7;
+        let replaced = index.insert("a.txt".into(), record.clone()).unwrap();
+        assert_eq!(replaced.uncompressed_size, 6);
+        assert!(index.insert("c.txt".into(), record.clone()).is_none());
+        index.set_duplicate_policy(DuplicatePolicy::FirstWins);
+        assert!(index.insert("a.txt".into(), record).is_none());
+        assert_eq!(index.get_all(Path::new("a.txt")).count(), 4);
+    }
+
+    #[test]
+    fn test_implicit_directories() {
+        let data = TestArchive::new()
+            .add("a/b/c.txt", b"hello")
+            .add("a/d.txt", b"world")
+            .add("e.txt", b"")
+            .add("x/", b"")
+            .finish();
+        let zip = ZipReader::new(Cursor::new(data)).unwrap();
+        let index = zip.index();
+        assert_eq!(
+            index.directories().collect::<Vec<_>>(),
+            [Path::new("a"), Path::new("a/b"), Path::new("x")]
+        );
+        assert_eq!(index.dirs().count(), 1);
+
+        let listing = index.read_dir(Path::new("a")).unwrap().collect::<Vec<_>>();
+        assert_eq!(listing.len(), 2);
+        assert!(listing[0].is_dir && listing[0].entry.is_none());
+        assert_eq!(listing[1].path, Path::new("a/d.txt"));
+        assert!(!listing[1].is_dir && listing[1].entry.is_some());
+        assert!(index.read_dir(Path::new("e.txt")).is_none());
+        assert_eq!(index.read_dir(Path::new("")).unwrap().count(), 3);
+
+        assert_eq!(index.parent(Path::new("a/b/c.txt")), Some(Path::new("a/b")));
+        assert_eq!(index.parent(Path::new("a")), Some(Path::new("")));
+        assert_eq!(index.parent(Path::new("missing")), None);
+
+        let walk = index.walk().map(|entry| entry.path).collect::<Vec<_>>();
+        assert_eq!(
+            walk,
+            ["a", "a/b", "a/b/c.txt", "a/d.txt", "e.txt", "x/"].map(Path::new)
+        );
+    }
+
+    #[test]
+    fn test_glob_and_prefix() {
+        let data = TestArchive::new()
+            .add("a/b.txt", b"")
+            .add("a/c/d.txt", b"")
+            .add("a/c/e.png", b"")
+            .add("a-b.txt", b"")
+            .add("ab/f.txt", b"")
+            .finish();
+        let zip = ZipReader::new(Cursor::new(data)).unwrap();
+        let index = zip.index();
+        let names = |iter: &mut dyn Iterator<Item = (&Path, &CentralDirectory)>| {
+            iter.map(|(path, _)| path.to_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(&mut index.prefix(Path::new("a"))),
+            ["a/b.txt", "a/c/d.txt", "a/c/e.png"]
+        );
+        assert_eq!(
+            names(&mut index.prefix(Path::new("a/c/e.png"))),
+            ["a/c/e.png"]
+        );
+        assert_eq!(
+            names(&mut index.glob("a/**/*.txt").unwrap()),
+            ["a/b.txt", "a/c/d.txt"]
+        );
+        assert_eq!(names(&mut index.glob("a?b.txt").unwrap()), ["a-b.txt"]);
+        assert_eq!(
+            names(&mut index.glob("[!a]*/*").unwrap()),
+            Vec::<String>::new()
+        );
+        assert!(index.glob("a/[").is_err());
+    }
+
+    #[test]
+    fn test_insensitive_lookup() {
+        let data = TestArchive::new()
+            .add("Docs/README.txt", b"readme")
+            .add_entry(TestEntry {
+                name: "Cafe\u{301}.txt",
+                flags: UTF8_FLAG,
+                contents: b"nfd",
+                ..Default::default()
+            })
+            .add("a.txt", b"lower")
+            .add("A.TXT", b"upper")
+            .finish();
+        let mut zip = ZipReader::new(Cursor::new(data)).unwrap();
+        assert!(matches!(
+            zip.index().find(Path::new("docs/readme.TXT")),
+            Err(ZipError::EntryNotFound(_))
+        ));
+
+        zip.set_lookup_mode(LookupMode::Insensitive);
+        assert_eq!(zip.dump_file(&"docs/readme.TXT").unwrap(), b"readme");
+        assert_eq!(zip.dump_file(&"caf\u{e9}.TXT").unwrap(), b"nfd");
+        assert_eq!(
+            zip.index().resolve(Path::new("a.txt")).unwrap_err(),
+            ZipError::AmbiguousPath("a.txt".into(), vec!["A.TXT".into(), "a.txt".into()])
+        );
+        assert_eq!(
+            zip.index().ambiguities().collect::<Vec<_>>(),
+            [[Path::new("A.TXT"), Path::new("a.txt")]]
+        );
+        // Exact lookups are not affected.
+        assert_eq!(
+            zip.index()
+                .get(Path::new("a.txt"))
+                .unwrap()
+                .uncompressed_size,
+            5
+        );
+    }
+}
@@ -0,0 +1,678 @@
+/*
+   Zip file reader and writer, in pure Rust.
+   Copyright (C) 2022 Matheus Xavier <mxavier@neonimp.com>
+
+   This program is free software: you can redistribute it and/or modify
+   it under the terms of the GNU Lesser General Public License as published by
+   the Free Software Foundation, either version 3 of the License, or
+   (at your option) any later version.
+
+   This program is distributed in the hope that it will be useful,
+   but WITHOUT ANY WARRANTY; without even the implied warranty of
+   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+   GNU General Public License for more details.
+
+   You should have received a copy of the GNU Lesser General Public License
+   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! A sidecar file holding the index of an archive, to reopen large archives
+//! without parsing their central directory again.
+//!
+//! The file starts with a magic, the format version and the kind of index it
+//! holds, then the [`ArchiveFingerprint`] of the archive it was made for, a
+//! cache with another version, kind or fingerprint is stale and ignored. The
+//! length and CRC-64 of the payload come next, a payload that doesn't match
+//! them is ignored too.
+//!
+//! The payload holds the index as it is in memory, so loading it doesn't
+//! parse, sort or look up anything per record. The byte strings are stored
+//! once in an arena at the start of the payload and referred to by offset and
+//! length, every other field is in little endian.
+
+use crc::{Crc, CRC_64_XZ};
+use neoncore::int_util::Endianness::LittleEndian;
+use neoncore::int_util::StreamReadInt;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::compact_index::{CompactEntry, CompactIndex};
+use crate::encoding::NameSource;
+use crate::index::{Names, Tree, ZipIndex};
+use crate::structures::CentralDirectory;
+use crate::{Result, ZipError};
+
+const MAGIC: &[u8; 4] = b"ZLIC";
+
+/// The version of the sidecar format, caches written by other versions are
+/// ignored.
+pub const INDEX_CACHE_VERSION: u16 = 2;
+
+/// The payload holds a [`ZipIndex`].
+const FULL_INDEX: u8 = 0;
+/// The payload holds a [`CompactIndex`].
+const COMPACT_INDEX: u8 = 1;
+
+/// Length of a [`CentralDirectory`] in the payload.
+const ENTRY_LEN: usize = 116;
+/// Length of a [`CompactEntry`] in the payload.
+const COMPACT_ENTRY_LEN: usize = 64;
+/// Length of a reference to the arena.
+const REF_LEN: usize = 12;
+
+const CRC_64: Crc<u64> = Crc::<u64>::new(&CRC_64_XZ);
+
+/// What ties a cached index to its archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArchiveFingerprint {
+    pub archive_size: u64,
+    /// Where the end of central directory record is in the stream.
+    pub eocd_offset: u64,
+    /// CRC-64 of the whole central directory.
+    pub cd_hash: u64,
+}
+
+impl ArchiveFingerprint {
+    /// Fingerprint the archive in `reader`, with its central directory
+    /// `cd_size` bytes long at `cd_offset` in the stream. Only the central
+    /// directory is read, which is much cheaper than parsing it.
+    pub fn compute<R: Read + Seek>(
+        reader: &mut R,
+        eocd_offset: u64,
+        cd_offset: u64,
+        cd_size: u64,
+    ) -> Result<Self> {
+        let archive_size = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(cd_offset))?;
+        let mut digest = CRC_64.digest();
+        let mut central_dir = reader.take(cd_size);
+        let mut buf = [0u8; 8192];
+        loop {
+            let read = central_dir.read(&mut buf)?;
+            if read == 0 {
+                break;
+            }
+            digest.update(&buf[..read]);
+        }
+        Ok(ArchiveFingerprint {
+            archive_size,
+            eocd_offset,
+            cd_hash: digest.finalize(),
+        })
+    }
+}
+
+/// Write `index` to `writer` as the cache of the archive with `fingerprint`.
+pub fn write_index_cache<W: Write>(
+    writer: &mut W,
+    index: &ZipIndex,
+    fingerprint: &ArchiveFingerprint,
+) -> Result<()> {
+    let (entries, names, tree) = index.parts();
+    let mut arena = Arena::default();
+    let mut out = Vec::new();
+    out.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+    for entry in entries {
+        write_entry(&mut out, &mut arena, entry);
+    }
+    out.extend_from_slice(&(names.len() as u64).to_le_bytes());
+    for (name, positions) in names {
+        arena.write_ref(&mut out, name.as_os_str().as_encoded_bytes());
+        out.extend_from_slice(&(positions.len() as u32).to_le_bytes());
+        for &position in positions {
+            out.extend_from_slice(&(position as u64).to_le_bytes());
+        }
+    }
+    out.extend_from_slice(&(tree.len() as u64).to_le_bytes());
+    for (dir, children) in tree {
+        arena.write_ref(&mut out, dir.as_os_str().as_encoded_bytes());
+        out.extend_from_slice(&(children.len() as u32).to_le_bytes());
+        for child in children {
+            arena.write_ref(&mut out, child.as_os_str().as_encoded_bytes());
+        }
+    }
+    write_cache(writer, FULL_INDEX, fingerprint, &arena.bytes, &out)
+}
+
+/// Read an index written by [`write_index_cache`], `None` if the cache was
+/// written by another version or for another archive than `fingerprint`, or
+/// if it's damaged.
+pub fn read_index_cache<R: Read>(
+    reader: &mut R,
+    fingerprint: &ArchiveFingerprint,
+) -> Result<Option<ZipIndex>> {
+    let payload = match read_cache(reader, FULL_INDEX, fingerprint)? {
+        Some(payload) => payload,
+        None => return Ok(None),
+    };
+    let (arena, mut data) = split_arena(&payload)?;
+    let data = &mut data;
+
+    let count = read_count(data, ENTRY_LEN)?;
+    let mut entries = Vec::with_capacity(count);
+    for _ in 0..count {
+        entries.push(read_entry(data, arena)?);
+    }
+    let count = read_count(data, REF_LEN + 4)?;
+    let mut names = Vec::with_capacity(count);
+    for _ in 0..count {
+        let name = read_path(data, arena)?;
+        let positions = (0..read_count32(data, 8)?)
+            .map(|_| match data.read_u64(LittleEndian)? as usize {
+                position if position < entries.len() => Ok(position),
+                _ => Err(invalid_cache("record position")),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if positions.is_empty() {
+            return Err(invalid_cache("name without records"));
+        }
+        names.push((name, positions));
+    }
+    let count = read_count(data, REF_LEN + 4)?;
+    let mut tree = Vec::with_capacity(count);
+    for _ in 0..count {
+        let dir = read_path(data, arena)?;
+        let children = (0..read_count32(data, REF_LEN)?)
+            .map(|_| read_path(data, arena))
+            .collect::<Result<Vec<_>>>()?;
+        tree.push((dir, children));
+    }
+    // The pairs were written sorted, which makes collecting them cheap.
+    let names = names.into_iter().collect::<Names>();
+    let tree = tree.into_iter().collect::<Tree>();
+    if !tree.contains_key(Path::new("")) {
+        return Err(invalid_cache("missing root directory"));
+    }
+    Ok(Some(ZipIndex::from_parts(entries, names, tree)))
+}
+
+/// Write `index` to `writer` as the cache of the archive with `fingerprint`,
+/// see [`write_index_cache`].
+pub fn write_compact_index_cache<W: Write>(
+    writer: &mut W,
+    index: &CompactIndex,
+    fingerprint: &ArchiveFingerprint,
+) -> Result<()> {
+    let (names, records, distinct) = index.parts();
+    let mut out = Vec::with_capacity(16 + records.len() * COMPACT_ENTRY_LEN);
+    out.extend_from_slice(&(records.len() as u64).to_le_bytes());
+    for record in records {
+        write_compact_entry(&mut out, record);
+    }
+    out.extend_from_slice(&(distinct as u64).to_le_bytes());
+    write_cache(writer, COMPACT_INDEX, fingerprint, names.as_bytes(), &out)
+}
+
+/// Read an index written by [`write_compact_index_cache`], see
+/// [`read_index_cache`].
+pub fn read_compact_index_cache<R: Read>(
+    reader: &mut R,
+    fingerprint: &ArchiveFingerprint,
+) -> Result<Option<CompactIndex>> {
+    let payload = match read_cache(reader, COMPACT_INDEX, fingerprint)? {
+        Some(payload) => payload,
+        None => return Ok(None),
+    };
+    let (names, mut data) = split_arena(&payload)?;
+    let names = std::str::from_utf8(names).map_err(|_| invalid_cache("names"))?;
+    let data = &mut data;
+
+    let count = read_count(data, COMPACT_ENTRY_LEN)?;
+    let mut records = Vec::with_capacity(count);
+    for _ in 0..count {
+        let record = read_compact_entry(data)?;
+        let start = record.name_start as usize;
+        if names.get(start..start + record.name_len as usize).is_none() {
+            return Err(invalid_cache("name"));
+        }
+        records.push(record);
+    }
+    let distinct = data.read_u64(LittleEndian)? as usize;
+    if distinct > records.len() {
+        return Err(invalid_cache("number of names"));
+    }
+    Ok(Some(CompactIndex::from_parts(
+        names.to_string(),
+        records,
+        distinct,
+    )))
+}
+
+/// Write the sidecar file at `path` with `write`. The data goes to a
+/// temporary file next to it, which replaces `path` once complete, so a
+/// sidecar is never seen half written.
+pub(crate) fn write_cache_file(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<()>,
+) -> Result<()> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let mut name = path
+        .file_name()
+        .ok_or_else(|| ZipError::IOError(std::io::ErrorKind::InvalidInput.into()))?
+        .to_os_string();
+    name.push(format!(
+        ".{}-{}.tmp",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let temp = path.with_file_name(name);
+    let written = File::create(&temp)
+        .map_err(ZipError::from)
+        .and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            write(&mut writer)?;
+            writer
+                .into_inner()
+                .map_err(|e| e.into_error())?
+                .sync_all()?;
+            Ok(std::fs::rename(&temp, path)?)
+        });
+    if written.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    written
+}
+
+fn write_cache<W: Write>(
+    writer: &mut W,
+    kind: u8,
+    fingerprint: &ArchiveFingerprint,
+    arena: &[u8],
+    data: &[u8],
+) -> Result<()> {
+    let arena_len = (arena.len() as u64).to_le_bytes();
+    let mut digest = CRC_64.digest();
+    digest.update(&arena_len);
+    digest.update(arena);
+    digest.update(data);
+
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&INDEX_CACHE_VERSION.to_le_bytes());
+    out.push(kind);
+    out.extend_from_slice(&fingerprint.archive_size.to_le_bytes());
+    out.extend_from_slice(&fingerprint.eocd_offset.to_le_bytes());
+    out.extend_from_slice(&fingerprint.cd_hash.to_le_bytes());
+    let payload_len = (arena_len.len() + arena.len() + data.len()) as u64;
+    out.extend_from_slice(&payload_len.to_le_bytes());
+    out.extend_from_slice(&digest.finalize().to_le_bytes());
+    out.extend_from_slice(&arena_len);
+    writer.write_all(&out)?;
+    writer.write_all(arena)?;
+    writer.write_all(data)?;
+    Ok(())
+}
+
+/// Read the payload of a sidecar holding an index of the `kind`, `None` if
+/// it's stale or damaged.
+fn read_cache<R: Read>(
+    reader: &mut R,
+    kind: u8,
+    fingerprint: &ArchiveFingerprint,
+) -> Result<Option<Vec<u8>>> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC
+        || reader.read_u16(LittleEndian)? != INDEX_CACHE_VERSION
+        || read_u8(reader)? != kind
+    {
+        return Ok(None);
+    }
+    let cached = ArchiveFingerprint {
+        archive_size: reader.read_u64(LittleEndian)?,
+        eocd_offset: reader.read_u64(LittleEndian)?,
+        cd_hash: reader.read_u64(LittleEndian)?,
+    };
+    if cached != *fingerprint {
+        return Ok(None);
+    }
+    let payload_len = reader.read_u64(LittleEndian)?;
+    let checksum = reader.read_u64(LittleEndian)?;
+    // Not trusting the length to allocate, a truncated payload is shorter.
+    let mut payload = Vec::new();
+    reader.take(payload_len).read_to_end(&mut payload)?;
+    if payload.len() as u64 != payload_len || CRC_64.checksum(&payload) != checksum {
+        return Ok(None);
+    }
+    Ok(Some(payload))
+}
+
+/// Split a payload into its arena and the data after it.
+fn split_arena(payload: &[u8]) -> Result<(&[u8], &[u8])> {
+    let mut data = payload;
+    let len = data.read_u64(LittleEndian)?;
+    if len > data.len() as u64 {
+        return Err(invalid_cache("arena length"));
+    }
+    Ok(data.split_at(len as usize))
+}
+
+/// The byte strings of a payload, each stored once.
+#[derive(Default)]
+struct Arena<'a> {
+    bytes: Vec<u8>,
+    starts: HashMap<&'a [u8], u64>,
+}
+
+impl<'a> Arena<'a> {
+    /// Add `bytes` to the arena and write a reference to them to `out`.
+    fn write_ref(&mut self, out: &mut Vec<u8>, bytes: &'a [u8]) {
+        let start = *self.starts.entry(bytes).or_insert_with(|| {
+            let start = self.bytes.len() as u64;
+            self.bytes.extend_from_slice(bytes);
+            start
+        });
+        out.extend_from_slice(&start.to_le_bytes());
+        out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    }
+}
+
+fn read_ref<'a>(data: &mut &[u8], arena: &'a [u8]) -> Result<&'a [u8]> {
+    let start = data.read_u64(LittleEndian)? as usize;
+    let len = data.read_u32(LittleEndian)? as usize;
+    start
+        .checked_add(len)
+        .and_then(|end| arena.get(start..end))
+        .ok_or_else(|| invalid_cache("arena reference"))
+}
+
+fn read_path(data: &mut &[u8], arena: &[u8]) -> Result<PathBuf> {
+    let bytes = read_ref(data, arena)?;
+    let name = std::str::from_utf8(bytes).map_err(|_| invalid_cache("name"))?;
+    Ok(PathBuf::from(name))
+}
+
+/// Read a count of items at least `item_len` bytes long, which can't be more
+/// than what's left of the payload, so it's safe to reserve.
+fn read_count(data: &mut &[u8], item_len: usize) -> Result<usize> {
+    let count = data.read_u64(LittleEndian)?;
+    if count > (data.len() / item_len) as u64 {
+        return Err(invalid_cache("count"));
+    }
+    Ok(count as usize)
+}
+
+/// Like [`read_count`] for counts stored in 4 bytes.
+fn read_count32(data: &mut &[u8], item_len: usize) -> Result<usize> {
+    let count = data.read_u32(LittleEndian)? as usize;
+    if count > data.len() / item_len {
+        return Err(invalid_cache("count"));
+    }
+    Ok(count)
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> Result<u8> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn invalid_cache(what: &str) -> ZipError {
+    ZipError::IOError(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("Invalid index cache: {what}"),
+    ))
+}
+
+fn write_entry<'a>(out: &mut Vec<u8>, arena: &mut Arena<'a>, entry: &'a CentralDirectory) {
+    out.extend_from_slice(&entry.offset.to_le_bytes());
+    out.extend_from_slice(&entry.version_made_by.to_le_bytes());
+    out.extend_from_slice(&entry.version_needed_to_extract.to_le_bytes());
+    out.extend_from_slice(&entry.flags.to_le_bytes());
+    out.extend_from_slice(&entry.compression.to_le_bytes());
+    out.extend_from_slice(&entry.last_mod_time.to_le_bytes());
+    out.extend_from_slice(&entry.last_mod_date.to_le_bytes());
+    out.extend_from_slice(&entry.crc32.to_le_bytes());
+    out.extend_from_slice(&entry.compressed_size.to_le_bytes());
+    out.extend_from_slice(&entry.uncompressed_size.to_le_bytes());
+    arena.write_ref(out, entry.filename.as_os_str().as_encoded_bytes());
+    arena.write_ref(out, &entry.raw_filename);
+    out.push(match entry.name_source {
+        NameSource::Utf8 => 0,
+        NameSource::Legacy => 1,
+        NameSource::UnicodeExtra => 2,
+    });
+    arena.write_ref(out, &entry.extra_field);
+    arena.write_ref(out, &entry.file_comment);
+    out.extend_from_slice(&entry.disk_number_start.to_le_bytes());
+    out.extend_from_slice(&entry.internal_file_attributes.to_le_bytes());
+    out.extend_from_slice(&entry.external_file_attributes.to_le_bytes());
+    out.extend_from_slice(&entry.local_header_rel_offset.to_le_bytes());
+    out.push(entry.is_directory as u8);
+    out.extend_from_slice(&entry.len.to_le_bytes());
+}
+
+fn read_entry(data: &mut &[u8], arena: &[u8]) -> Result<CentralDirectory> {
+    Ok(CentralDirectory {
+        offset: data.read_u64(LittleEndian)?,
+        version_made_by: data.read_u16(LittleEndian)?,
+        version_needed_to_extract: data.read_u16(LittleEndian)?,
+        flags: data.read_u16(LittleEndian)?,
+        compression: data.read_u16(LittleEndian)?,
+        last_mod_time: data.read_u16(LittleEndian)?,
+        last_mod_date: data.read_u16(LittleEndian)?,
+        crc32: data.read_u32(LittleEndian)?,
+        compressed_size: data.read_u64(LittleEndian)?,
+        uncompressed_size: data.read_u64(LittleEndian)?,
+        filename: read_path(data, arena)?,
+        raw_filename: read_ref(data, arena)?.to_vec(),
+        name_source: match read_u8(data)? {
+            0 => NameSource::Utf8,
+            1 => NameSource::Legacy,
+            2 => NameSource::UnicodeExtra,
+            _ => return Err(invalid_cache("name source")),
+        },
+        extra_field: read_ref(data, arena)?.to_vec(),
+        file_comment: read_ref(data, arena)?.to_vec(),
+        disk_number_start: data.read_u32(LittleEndian)?,
+        internal_file_attributes: data.read_u16(LittleEndian)?,
+        external_file_attributes: data.read_u32(LittleEndian)?,
+        local_header_rel_offset: data.read_u64(LittleEndian)?,
+        is_directory: read_u8(data)? != 0,
+        len: data.read_u64(LittleEndian)?,
+    })
+}
+
+fn write_compact_entry(out: &mut Vec<u8>, entry: &CompactEntry) {
+    out.extend_from_slice(&entry.name_start.to_le_bytes());
+    out.extend_from_slice(&entry.name_len.to_le_bytes());
+    out.extend_from_slice(&entry.cd_offset.to_le_bytes());
+    out.extend_from_slice(&entry.local_header_offset.to_le_bytes());
+    out.extend_from_slice(&entry.compressed_size.to_le_bytes());
+    out.extend_from_slice(&entry.uncompressed_size.to_le_bytes());
+    out.extend_from_slice(&entry.crc32.to_le_bytes());
+    out.extend_from_slice(&entry.external_file_attributes.to_le_bytes());
+    out.extend_from_slice(&entry.version_made_by.to_le_bytes());
+    out.extend_from_slice(&entry.flags.to_le_bytes());
+    out.extend_from_slice(&entry.compression.to_le_bytes());
+    out.extend_from_slice(&entry.last_mod_time.to_le_bytes());
+    out.extend_from_slice(&entry.last_mod_date.to_le_bytes());
+    // Padded to a round length.
+    out.extend_from_slice(&[entry.is_directory as u8, 0]);
+}
+
+fn read_compact_entry(data: &mut &[u8]) -> Result<CompactEntry> {
+    let entry = CompactEntry {
+        name_start: data.read_u64(LittleEndian)?,
+        name_len: data.read_u32(LittleEndian)?,
+        cd_offset: data.read_u64(LittleEndian)?,
+        local_header_offset: data.read_u64(LittleEndian)?,
+        compressed_size: data.read_u64(LittleEndian)?,
+        uncompressed_size: data.read_u64(LittleEndian)?,
+        crc32: data.read_u32(LittleEndian)?,
+        external_file_attributes: data.read_u32(LittleEndian)?,
+        version_made_by: data.read_u16(LittleEndian)?,
+        flags: data.read_u16(LittleEndian)?,
+        compression: data.read_u16(LittleEndian)?,
+        last_mod_time: data.read_u16(LittleEndian)?,
+        last_mod_date: data.read_u16(LittleEndian)?,
+        is_directory: read_u8(data)? != 0,
+    };
+    read_u8(data)?;
+    Ok(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compact_index::CompactZipReader;
+    use crate::encoding::Cp437;
+    use crate::reader::ZipReader;
+    use crate::test_util::{TempDir, TestArchive, TestEntry};
+    use std::io::{BufReader, Cursor};
+
+    fn test_archive() -> Vec<u8> {
+        TestArchive::new()
+            .add("a/b.txt", b"hello")
+            .add_entry(TestEntry {
+                name: "c.txt",
+                contents: b"world",
+                comment: b"a comment",
+                ..Default::default()
+            })
+            .add("c.txt", b"again")
+            .finish()
+    }
+
+    #[test]
+    fn test_index_cache() {
+        let data = test_archive();
+        let dir = TempDir::new("cache");
+        let sidecar = dir.path().join("archive.idx");
+
+        // Written on the first open, then read back.
+        let mut zip = ZipReader::new_cached(Cursor::new(data.clone()), &sidecar).unwrap();
+        assert!(zip.cache_error().is_none());
+        assert_eq!(zip.dump_file(&"c.txt").unwrap(), b"again");
+        let fingerprint = zip.fingerprint().unwrap();
+        let mut cache = BufReader::new(File::open(&sidecar).unwrap());
+        let cached = read_index_cache(&mut cache, &fingerprint).unwrap().unwrap();
+        let entries = cached.get_all(Path::new("c.txt")).collect::<Vec<_>>();
+        let original = zip.index().get(Path::new("c.txt")).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].file_comment, b"a comment");
+        assert_eq!(
+            entries[1].local_header_rel_offset,
+            original.local_header_rel_offset
+        );
+        assert_eq!(entries[1].len, original.len);
+        assert!(cached.is_dir(Path::new("a")));
+        let walk = |index: &ZipIndex| {
+            index
+                .walk()
+                .map(|entry| entry.path.to_path_buf())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(walk(&cached), walk(zip.index()));
+
+        let mut record = Vec::new();
+        write_entry(&mut record, &mut Arena::default(), original);
+        assert_eq!(record.len(), ENTRY_LEN);
+
+        // A damaged sidecar is ignored and written again.
+        let mut damaged = std::fs::read(&sidecar).unwrap();
+        *damaged.last_mut().unwrap() ^= 1;
+        std::fs::write(&sidecar, &damaged).unwrap();
+        let mut cache = Cursor::new(damaged);
+        assert!(read_index_cache(&mut cache, &fingerprint)
+            .unwrap()
+            .is_none());
+        let mut zip = ZipReader::new_cached(Cursor::new(data.clone()), &sidecar).unwrap();
+        assert_eq!(zip.dump_file(&"a/b.txt").unwrap(), b"hello");
+        let mut cache = BufReader::new(File::open(&sidecar).unwrap());
+        assert!(read_index_cache(&mut cache, &fingerprint)
+            .unwrap()
+            .is_some());
+
+        // Another archive makes the cache stale, it's rebuilt.
+        let other = TestArchive::new().add("d.txt", b"other").finish();
+        let mut zip = ZipReader::new_cached(Cursor::new(other), &sidecar).unwrap();
+        assert_ne!(zip.fingerprint().unwrap(), fingerprint);
+        let mut cache = BufReader::new(File::open(&sidecar).unwrap());
+        assert!(read_index_cache(&mut cache, &fingerprint)
+            .unwrap()
+            .is_none());
+        assert_eq!(zip.dump_file(&"d.txt").unwrap(), b"other");
+        assert!(!zip.index().contains(Path::new("c.txt")));
+
+        // Failing to write the sidecar doesn't fail the open, the error is
+        // kept.
+        let unwritable = dir.path().join("missing").join("cache.idx");
+        let mut zip = ZipReader::new_cached(Cursor::new(data.clone()), &unwritable).unwrap();
+        assert_eq!(zip.dump_file(&"a/b.txt").unwrap(), b"hello");
+        assert!(matches!(zip.cache_error(), Some(ZipError::IOError(_))));
+        assert!(zip.save_index(&unwritable).is_err());
+
+        // No temporary file is left behind.
+        let files = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(files, ["archive.idx"]);
+
+        // The names are decoded with the decoder given, also when the index
+        // is read from the sidecar.
+        let legacy = TestArchive::new()
+            .add_entry(TestEntry {
+                name: "",
+                raw_name: Some(b"\x81ber.txt"),
+                contents: b"legacy",
+                ..Default::default()
+            })
+            .finish();
+        let latin1 = |bytes: &[u8]| bytes.iter().map(|&b| b as char).collect::<String>();
+        let sidecar = dir.path().join("legacy.idx");
+        for _ in 0..2 {
+            let zip = ZipReader::new_cached_with_legacy_decoder(
+                Cursor::new(legacy.clone()),
+                &sidecar,
+                latin1,
+            )
+            .unwrap();
+            assert!(zip.index().contains(Path::new("\u{81}ber.txt")));
+        }
+        let zip = ZipReader::new_cached_with_legacy_decoder(
+            Cursor::new(legacy),
+            dir.path().join("cp437.idx"),
+            Cp437,
+        )
+        .unwrap();
+        assert!(zip.index().contains(Path::new("über.txt")));
+    }
+
+    #[test]
+    fn test_compact_index_cache() {
+        let data = test_archive();
+        let dir = TempDir::new("compact-cache");
+        let sidecar = dir.path().join("archive.idx");
+
+        let mut zip = CompactZipReader::new_cached(Cursor::new(data.clone()), &sidecar).unwrap();
+        assert!(zip.cache_error().is_none());
+        let fingerprint = zip.fingerprint().unwrap();
+        let mut cache = BufReader::new(File::open(&sidecar).unwrap());
+        let cached = read_compact_index_cache(&mut cache, &fingerprint)
+            .unwrap()
+            .unwrap();
+        assert_eq!(cached.entries(), zip.index().entries());
+        assert_eq!(cached.len(), 2);
+        assert_eq!(cached.get_all(Path::new("c.txt")).len(), 2);
+        assert!(
+            read_index_cache(&mut File::open(&sidecar).unwrap(), &fingerprint)
+                .unwrap()
+                .is_none()
+        );
+
+        // Reopened from the sidecar.
+        let mut zip = CompactZipReader::new_cached(Cursor::new(data.clone()), &sidecar).unwrap();
+        assert_eq!(zip.dump_file(&"c.txt").unwrap(), b"again");
+        assert_eq!(zip.entry_comment(&"c.txt").unwrap(), None);
+
+        let unwritable = dir.path().join("missing").join("cache.idx");
+        let zip = CompactZipReader::new_cached(Cursor::new(data), &unwritable).unwrap();
+        assert!(zip.cache_error().is_some());
+        assert_eq!(zip.index().len(), 2);
+    }
+}
@@ -1,4 +1,4 @@
-/**!
+/*
    Zip file reader and writer, in pure Rust.
    Copyright (C) 2022 Matheus Xavier <mxavier@neonimp.com>
 
@@ -15,30 +15,55 @@
    You should have received a copy of the GNU Lesser General Public License
    along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
+use crc::{Crc, CRC_32_ISO_HDLC};
 use std::collections::HashMap;
 use std::ffi::OsString;
 use std::fs::File;
 use std::path::PathBuf;
 use thiserror::Error;
 
+pub mod attributes;
 pub mod codecs;
+pub mod compact_index;
 pub mod compression_codecs;
+pub mod datetime;
+pub mod encoding;
+pub mod extra_field;
 #[cfg(feature = "ffi")]
 pub mod ffi;
+pub mod glob;
+pub mod index;
+pub mod index_cache;
+pub mod multi_volume;
 pub mod reader;
+pub mod scanner;
+pub mod stream_reader;
 pub mod structures;
+#[cfg(test)]
+mod test_util;
 pub mod writer;
 
 pub const EOCD_SIG: u32 = 0x06054b50;
 pub const EOCD64_SIG: u32 = 0x06064b50;
+pub const EOCD64_LOCATOR_SIG: u32 = 0x07064b50;
 pub const CD_SIG: u32 = 0x02014b50;
 pub const LFH_SIG: u32 = 0x04034b50;
 pub const DD_SIG: u32 = 0x08074b50;
 
+/// The CRC-32 variant used by the zip format.
+pub(crate) static ZIP_CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// Header id of the Zip64 extended information extra field.
+pub const ZIP64_EXTRA_ID: u16 = 0x0001;
+/// Header id of the Info-ZIP Unicode Path extra field.
+pub const UNICODE_PATH_EXTRA_ID: u16 = 0x7075;
+/// Header id of the Info-ZIP Unicode Comment extra field.
+pub const UNICODE_COMMENT_EXTRA_ID: u16 = 0x6375;
+
 #[derive(Debug, Error)]
 pub enum ZipError {
     #[error("IO exception: {0}")]
-    IOError(#[from] std::io::Error),
+    IOError(std::io::Error),
     #[error("Invalid signature: {0}")]
     InvalidSignature(u32),
     #[error("Entry not found: {0}")]
@@ -55,6 +80,18 @@ pub enum ZipError {
     InvalidCompressionLevel(i32),
     #[error("Invalid UTF-8 string: {0}")]
     InvalidUtf8String(#[from] std::string::FromUtf8Error),
+    #[error("Central directory size mismatch: {0} expected, {1} found")]
+    CentralDirectorySizeMismatch(u64, u64),
+    #[error("CRC-32 mismatch for {0}: {1:08X} expected, {2:08X} found")]
+    CrcMismatch(PathBuf, u32, u32),
+    #[error("Volume {0} of the archive is missing")]
+    MissingVolume(u32),
+    #[error("Invalid pattern {0}: {1}")]
+    InvalidPattern(String, String),
+    #[error("Ambiguous path {0}, it matches {1:?}")]
+    AmbiguousPath(PathBuf, Vec<PathBuf>),
+    #[error("Unsafe path {0}, it would be extracted outside of the destination")]
+    UnsafePath(PathBuf),
     #[error("Fatal Error: {0}, {1}")]
     UnknownError(u64, String),
 }
@@ -71,11 +108,34 @@ impl ZipError {
             ZipError::MismatchedCompressionMethod(_, _) => 7,
             ZipError::InvalidCompressionLevel(_) => 8,
             ZipError::InvalidUtf8String(_) => 9,
+            ZipError::CentralDirectorySizeMismatch(_, _) => 10,
+            ZipError::CrcMismatch(_, _, _) => 11,
+            ZipError::MissingVolume(_) => 12,
+            ZipError::InvalidPattern(_, _) => 13,
+            ZipError::AmbiguousPath(_, _) => 14,
+            ZipError::UnsafePath(_) => 15,
             ZipError::UnknownError(_, _) => !0,
         }
     }
 }
 
+/// Errors raised while reading an entry through [`std::io::Read`] have to be
+/// wrapped in an [`std::io::Error`], unwrap them so callers get the original.
+impl From<std::io::Error> for ZipError {
+    fn from(e: std::io::Error) -> Self {
+        // Errors without a payload, like the OS errors, are kept as they are.
+        if e.get_ref().is_none() {
+            return ZipError::IOError(e);
+        }
+        let kind = e.kind();
+        match e.into_inner().map(|inner| inner.downcast::<ZipError>()) {
+            Some(Ok(inner)) => *inner,
+            Some(Err(other)) => ZipError::IOError(std::io::Error::new(kind, other)),
+            None => ZipError::IOError(kind.into()),
+        }
+    }
+}
+
 impl PartialEq for ZipError {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
@@ -85,6 +145,17 @@ impl PartialEq for ZipError {
                 true
             }
             (ZipError::InvalidEntry(a), ZipError::InvalidEntry(b)) => a == b,
+            (
+                ZipError::CentralDirectorySizeMismatch(a, b),
+                ZipError::CentralDirectorySizeMismatch(c, d),
+            ) => a == c && b == d,
+            (ZipError::CrcMismatch(a, b, c), ZipError::CrcMismatch(d, e, f)) => {
+                a == d && b == e && c == f
+            }
+            (ZipError::MissingVolume(a), ZipError::MissingVolume(b)) => a == b,
+            (ZipError::InvalidPattern(a, b), ZipError::InvalidPattern(c, d)) => a == c && b == d,
+            (ZipError::AmbiguousPath(a, b), ZipError::AmbiguousPath(c, d)) => a == c && b == d,
+            (ZipError::UnsafePath(a), ZipError::UnsafePath(b)) => a == b,
             (ZipError::UnknownError(a, b), ZipError::UnknownError(c, d)) => a == c && b == d,
             _ => false,
         }
@@ -0,0 +1,169 @@
+/*
+   Zip file reader and writer, in pure Rust.
+   Copyright (C) 2022 Matheus Xavier <mxavier@neonimp.com>
+
+   This program is free software: you can redistribute it and/or modify
+   it under the terms of the GNU Lesser General Public License as published by
+   the Free Software Foundation, either version 3 of the License, or
+   (at your option) any later version.
+
+   This program is distributed in the hope that it will be useful,
+   but WITHOUT ANY WARRANTY; without even the implied warranty of
+   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+   GNU General Public License for more details.
+
+   You should have received a copy of the GNU Lesser General Public License
+   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Split and spanned archives, where each disk of the archive is a separate
+//! file, like the `.z01 ... .zip` sets made by WinZip or `zip -s`.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::Result;
+
+/// Presents the parts of a multi-volume archive as one logical stream, with
+/// the parts laid out back to back in disk order.
+pub struct MultiVolumeReader<R: Read + Seek> {
+    parts: Vec<R>,
+    /// Where each part starts in the logical stream.
+    starts: Vec<u64>,
+    len: u64,
+    pos: u64,
+}
+
+impl<R: Read + Seek> MultiVolumeReader<R> {
+    /// Join `parts`, which must be in disk order, into one stream.
+    pub fn new(mut parts: Vec<R>) -> Result<Self> {
+        let mut starts = Vec::with_capacity(parts.len());
+        let mut len = 0;
+        for part in parts.iter_mut() {
+            starts.push(len);
+            len += part.seek(SeekFrom::End(0))?;
+        }
+
+        Ok(MultiVolumeReader {
+            parts,
+            starts,
+            len,
+            pos: 0,
+        })
+    }
+
+    /// Where each disk starts in the logical stream, indexed by disk number.
+    pub fn volume_starts(&self) -> &[u64] {
+        &self.starts
+    }
+
+    /// The number of parts in the archive.
+    pub fn volumes(&self) -> usize {
+        self.parts.len()
+    }
+
+    pub fn into_inner(self) -> Vec<R> {
+        self.parts
+    }
+}
+
+impl MultiVolumeReader<File> {
+    /// Open a split archive from the path of its last part, the `.zip` file.
+    /// The other parts are expected next to it with the same name and the
+    /// extensions `.z01`, `.z02` and so on.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let mut parts = Vec::new();
+        for disk in 1.. {
+            let part = path.with_extension(format!("z{:02}", disk));
+            if !part.exists() {
+                break;
+            }
+            parts.push(File::open(part)?);
+        }
+        parts.push(File::open(path)?);
+        Self::new(parts)
+    }
+}
+
+impl<R: Read + Seek> Read for MultiVolumeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        // The last part starting at or before the position, empty parts are
+        // skipped since the next part starts at the same place.
+        let part = self.starts.partition_point(|&start| start <= self.pos) - 1;
+        let part_end = self.starts.get(part + 1).copied().unwrap_or(self.len);
+        let len = buf.len().min((part_end - self.pos) as usize);
+
+        let reader = &mut self.parts[part];
+        reader.seek(SeekFrom::Start(self.pos - self.starts[part]))?;
+        let read = reader.read(&mut buf[..len])?;
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl<R: Read + Seek> Seek for MultiVolumeReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+        };
+        self.pos = pos.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression_codecs::NoCompressionCodec;
+    use crate::reader::ZipReader;
+    use crate::test_util::TestArchive;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_read_across_parts() {
+        let parts = vec![
+            Cursor::new(b"hel".to_vec()),
+            Cursor::new(Vec::new()),
+            Cursor::new(b"lo wor".to_vec()),
+            Cursor::new(b"ld".to_vec()),
+        ];
+        let mut reader = MultiVolumeReader::new(parts).unwrap();
+        assert_eq!(reader.volume_starts(), &[0, 3, 3, 9]);
+
+        let mut out = String::new();
+        reader.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "hello world");
+
+        reader.seek(SeekFrom::End(-8)).unwrap();
+        let mut buf = [0u8; 5];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"lo wo");
+    }
+
+    #[test]
+    fn test_split_archive() {
+        let parts = TestArchive::new()
+            .add("a.txt", b"hello")
+            .add("b.txt", b"world")
+            .finish_split();
+        let parts = parts.into_iter().map(Cursor::new).collect();
+        let mut zip = ZipReader::new_multi_volume(parts).unwrap();
+        assert_eq!(zip.index().len(), 2);
+        assert_eq!(
+            zip.extract_file(&"b.txt", &mut NoCompressionCodec).unwrap(),
+            b"world"
+        );
+    }
+}
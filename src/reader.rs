@@ -16,196 +16,230 @@
    along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
 
+use crc::Digest;
+use memchr::memmem;
 use neoncore::int_util::Endianness::LittleEndian;
 use neoncore::int_util::StreamReadInt;
-#[cfg(feature = "multi-thread")]
-use rayon::prelude::*;
-use std::collections::BTreeMap;
+use std::cell::Cell;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
-use std::path::{Path, PathBuf};
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::SystemTime;
 
+use crate::attributes::{self, FileType, HostOs};
+use crate::compact_index::{CompactEntry, CompactIndex};
 use crate::compression_codecs::CompressionCodec;
-use crate::structures::{CentralDirectory, EndOfCentralDirectory, EndOfCentralDirectory64, LocalFileHeader, ZipEntry};
-use crate::{Result, ZipError, CD_SIG, EOCD_SIG, LFH_SIG, EOCD64_SIG};
-
-pub struct ZipIndex(BTreeMap<PathBuf, CentralDirectory>);
-
-impl ZipIndex {
-    pub fn new(map: BTreeMap<PathBuf, CentralDirectory>) -> Self {
-        ZipIndex(map)
-    }
-
-    pub fn files(&self) -> impl Iterator<Item = &CentralDirectory> {
-        self.0.iter().filter_map(
-            |(_path, info)| {
-                if !info.is_directory {
-                    Some(info)
-                } else {
-                    None
-                }
-            },
-        )
-    }
-
-    pub fn dirs(&self) -> impl Iterator<Item = &CentralDirectory> {
-        self.0.iter().filter_map(
-            |(_path, info)| {
-                if info.is_directory {
-                    Some(info)
-                } else {
-                    None
-                }
-            },
-        )
-    }
-
-    pub fn get(&self, path: &Path) -> Option<&CentralDirectory> {
-        self.0.get(path)
-    }
-
-    pub fn insert(&mut self, path: PathBuf, info: CentralDirectory) -> Option<CentralDirectory> {
-        self.0.insert(path, info)
-    }
-
-    pub fn contains(&self, path: &Path) -> bool {
-        self.0.contains_key(path)
-    }
-
-    pub fn len(&self) -> usize {
-        self.0.len()
-    }
-
-    pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
-    }
-
-    pub fn iter(&self) -> impl Iterator<Item = (&Path, &CentralDirectory)> {
-        self.0.iter().map(|(path, info)| (path.as_path(), info))
-    }
-
-    pub fn keys(&self) -> impl Iterator<Item = &Path> {
-        self.0.keys().map(|path| path.as_path())
-    }
-
-    pub fn values(&self) -> impl Iterator<Item = &CentralDirectory> {
-        self.0.values()
-    }
-
-    pub fn into_keys(self) -> impl Iterator<Item = PathBuf> {
-        self.0.into_keys()
-    }
-
-    pub fn into_values(self) -> impl Iterator<Item = CentralDirectory> {
-        self.0.into_values()
-    }
-}
-
-impl IntoIterator for ZipIndex {
-    type Item = (PathBuf, CentralDirectory);
-    type IntoIter = std::collections::btree_map::IntoIter<PathBuf, CentralDirectory>;
-
-    fn into_iter(self) -> Self::IntoIter {
-        self.0.into_iter()
-    }
-}
+use crate::datetime::{from_filetime, from_unix_secs, DosDateTime, TimeZone};
+use crate::encoding::{
+    decode_text, decode_with_extra, Cp437, LegacyDecoder, NameSource, UTF8_FLAG,
+};
+use crate::extra_field::{ExtraField, ExtraFields};
+use crate::glob::EntryFilter;
+pub use crate::index::ZipIndex;
+use crate::index::{DuplicatePolicy, LookupMode};
+use crate::index_cache::{
+    read_index_cache, write_cache_file, write_index_cache, ArchiveFingerprint,
+};
+use crate::multi_volume::MultiVolumeReader;
+use crate::structures::{
+    CentralDirectory, DataDescriptor, EndOfCentralDirectory, EndOfCentralDirectory64,
+    EndOfCentralDirectory64Locator, LocalFileHeader,
+};
+use crate::{
+    Result, ZipError, CD_SIG, DD_SIG, EOCD64_LOCATOR_SIG, EOCD64_SIG, EOCD_SIG, LFH_SIG,
+    UNICODE_COMMENT_EXTRA_ID, UNICODE_PATH_EXTRA_ID, ZIP_CRC,
+};
 
 pub struct ZipReader<R: Read + Seek> {
     reader: BufReader<R>,
     index: ZipIndex,
-    is_zip64: bool,
+    archive_offset: u64,
+    eocd: EndOfCentralDirectory,
+    eocd64: Option<EndOfCentralDirectory64>,
+    /// Where the central directory is in the stream.
+    central_dir: Range<u64>,
+    verify_crc: bool,
+    legacy_decoder: Arc<dyn LegacyDecoder>,
+    time_zone: TimeZone,
+    /// Why the sidecar given to [`ZipReader::new_cached`] couldn't be written.
+    cache_error: Option<ZipError>,
 }
 
 pub struct ZipEntryInfo {
     pub name: PathBuf,
+    /// Where the name was decoded from, the header or the Unicode Path extra
+    /// field.
+    pub name_source: NameSource,
     pub is_dir: bool,
     pub is_file: bool,
     pub is_symlink: bool,
+    /// The system that made the entry.
+    pub host_os: HostOs,
+    pub file_type: FileType,
+    /// The Unix `st_mode`, with the permission bits, for entries made on a
+    /// Unix-like system.
+    pub unix_mode: Option<u32>,
     pub is_compressed: bool,
     pub size: u64,
     pub compressed_size: u64,
     pub crc32: u32,
     pub compression_method: u16,
-    pub last_modified: u32,
-    pub last_accessed: u32,
+    /// The modification time as stored in the header.
+    pub last_modified: DosDateTime,
+    /// The modification time, from the NTFS or extended timestamp extra
+    /// fields if present, or the DOS timestamp, `None` if that's invalid.
+    pub mtime: Option<SystemTime>,
+    /// The access time, from the NTFS extra field. The central directory copy
+    /// of the extended timestamp field leaves it out, see
+    /// [`ZipReader::local_file_info`] for the local header copy.
+    pub atime: Option<SystemTime>,
+    /// The creation time, from the NTFS extra field. The central directory
+    /// copy of the extended timestamp field leaves it out, see
+    /// [`ZipReader::local_file_info`] for the local header copy.
+    pub ctime: Option<SystemTime>,
     pub comment: Option<String>,
     pub offset: u64,
 }
 
 impl ZipEntryInfo {
-    pub(crate) fn from_central_dir(entry: &CentralDirectory) -> Self {
+    pub(crate) fn from_central_dir(
+        entry: &CentralDirectory,
+        legacy: &dyn LegacyDecoder,
+        time_zone: TimeZone,
+    ) -> Self {
+        let fields = entry.extra_fields().collect::<Vec<_>>();
+        Self::from_extra_fields(entry, &fields, legacy, time_zone)
+    }
+
+    /// Like [`ZipEntryInfo::from_central_dir`], taking the times from
+    /// `fields` instead of the central directory extra field.
+    fn from_extra_fields(
+        entry: &CentralDirectory,
+        fields: &[ExtraField],
+        legacy: &dyn LegacyDecoder,
+        time_zone: TimeZone,
+    ) -> Self {
+        let file_type = entry.file_type();
+        let last_modified = DosDateTime::new(entry.last_mod_date, entry.last_mod_time);
+        let (mut mtime, mut atime, mut ctime) = (None, None, None);
+        // The NTFS times are the most precise, then the extended timestamps,
+        // so the first source to provide a time wins.
+        let fill = |time: &mut Option<SystemTime>, from: Option<SystemTime>| {
+            if time.is_none() {
+                *time = from;
+            }
+        };
+        for field in fields {
+            if let ExtraField::Ntfs {
+                mtime: m,
+                atime: a,
+                ctime: c,
+            } = field
+            {
+                fill(&mut mtime, from_filetime(*m));
+                fill(&mut atime, from_filetime(*a));
+                fill(&mut ctime, from_filetime(*c));
+            }
+        }
+        for field in fields {
+            if let ExtraField::ExtendedTimestamp {
+                mtime: m,
+                atime: a,
+                ctime: c,
+                ..
+            } = field
+            {
+                fill(&mut mtime, m.and_then(|m| from_unix_secs(m as i64)));
+                fill(&mut atime, a.and_then(|a| from_unix_secs(a as i64)));
+                fill(&mut ctime, c.and_then(|c| from_unix_secs(c as i64)));
+            }
+        }
+        fill(&mut mtime, last_modified.to_system_time(time_zone));
+        let comment = entry_comment(entry, legacy);
         ZipEntryInfo {
             name: entry.filename.clone(),
-            is_dir: entry.external_file_attributes & 0x10 == 0x10,
-            is_file: entry.external_file_attributes & 0x20 == 0x20,
-            is_symlink: entry.external_file_attributes & 0x40000000 == 0x40000000,
+            name_source: entry.name_source,
+            is_dir: entry.is_directory,
+            is_file: file_type == FileType::Regular,
+            is_symlink: file_type == FileType::Symlink,
+            host_os: entry.host_os(),
+            file_type,
+            unix_mode: entry.unix_mode(),
             is_compressed: entry.compression != 0,
-            size: entry.uncompressed_size as u64,
-            compressed_size: entry.compressed_size as u64,
+            size: entry.uncompressed_size,
+            compressed_size: entry.compressed_size,
             crc32: entry.crc32,
             compression_method: entry.compression,
-            last_modified: entry.last_mod_date as u32,
-            last_accessed: entry.last_mod_date as u32,
-            offset: entry.local_header_rel_offset as u64,
-            comment: None,
+            last_modified,
+            mtime,
+            atime,
+            ctime,
+            offset: entry.local_header_rel_offset,
+            comment,
         }
     }
 }
 
-fn find_next_signature<R: Read + Seek>(
-    reader: &mut R,
-    signature: u32,
-    hint: Option<u64>,
-) -> Result<u64> {
-    let offset;
-    let start_pos = reader.stream_position()?;
-    // Split the signature into top and bottom u16s
-    let sig_lower = (signature & 0x0000FFFF) as u16;
-    let sig_upper = ((signature & 0xFFFF0000) >> 16) as u16;
-    let sig_fbyte = (signature & 0x000000FF) as u8;
-
-    if let Some(hint) = hint {
-        reader.seek(SeekFrom::Start(hint))?;
-    }
-
-    // Scan byte by byte until we find the first byte of the signature
-    loop {
-        let mut byte = [0u8];
-        let mut next_byte = [0u8];
-        reader.read_exact(&mut byte)?;
-        if byte[0] == sig_fbyte {
-            // If the first byte matches, read the next byte and check if it matches the lower
-            // u16 of the signature
-            reader.read_exact(&mut next_byte)?;
-            let lower = ((next_byte[0] as u16) << 8) | byte[0] as u16;
-            if lower == sig_lower {
-                // get the upper u16 of the signature candidate
-                let upper = reader.read_u16(LittleEndian)?;
-                if upper == sig_upper {
-                    // If the upper u16 matches, we found the signature
-                    offset = reader.stream_position()? - 4;
-                    break;
-                }
-            }
-        }
+/// Decode the comment of an entry, `None` if it has none.
+pub(crate) fn entry_comment(
+    entry: &CentralDirectory,
+    legacy: &dyn LegacyDecoder,
+) -> Option<String> {
+    (!entry.file_comment.is_empty()).then(|| {
+        decode_with_extra(
+            &entry.file_comment,
+            entry.flags,
+            &entry.extra_field,
+            UNICODE_COMMENT_EXTRA_ID,
+            legacy,
+        )
+        .0
+    })
+}
+
+/// Size of the end of central directory record, without the comment.
+const EOCD_LEN: u64 = 22;
+
+/// The eocd comment length is a u16, so the record can't start further than
+/// this from the end of the archive.
+const EOCD_SEARCH_WINDOW: u64 = EOCD_LEN + u16::MAX as u64;
+
+/// Locate the end of central directory by scanning backwards from the end of
+/// the archive, only accepting a candidate if its comment ends exactly at EOF.
+fn find_eocd_offset<T: Read + Seek>(data: &mut BufReader<T>) -> Result<u64> {
+    let archive_len = data.seek(SeekFrom::End(0))?;
+    if archive_len < EOCD_LEN {
+        return Err(ZipError::EndOfCentralDirectoryNotFound);
     }
+    let window_start = archive_len - archive_len.min(EOCD_SEARCH_WINDOW);
+    let mut window = vec![0u8; (archive_len - window_start) as usize];
+    data.seek(SeekFrom::Start(window_start))?;
+    data.read_exact(&mut window)?;
 
-    // Rewind the reader to the original position
-    reader.seek(SeekFrom::Start(start_pos))?;
-    Ok(offset)
+    memmem::rfind_iter(&window, &EOCD_SIG.to_le_bytes())
+        .find(|&pos| {
+            let Some(record) = window.get(pos..pos + EOCD_LEN as usize) else {
+                return false;
+            };
+            let comment_len = u16::from_le_bytes([record[20], record[21]]) as usize;
+            pos + EOCD_LEN as usize + comment_len == window.len()
+        })
+        .map(|pos| window_start + pos as u64)
+        .ok_or(ZipError::EndOfCentralDirectoryNotFound)
 }
 
 fn find_eocd<T: Read + Seek>(data: &mut BufReader<T>) -> Result<EndOfCentralDirectory> {
     let eocd: Option<EndOfCentralDirectory>;
-    let offset = match find_next_signature(data, EOCD_SIG, None) {
-        Ok(offset) => offset,
-        Err(_) => return Err(ZipError::EndOfCentralDirectoryNotFound),
-    };
+    let offset = find_eocd_offset(data)?;
     data.seek(SeekFrom::Start(offset))?;
     let sig_candidate = data.read_u32(LittleEndian)?;
 
     if sig_candidate == EOCD_SIG {
         eocd = Some(EndOfCentralDirectory {
+            offset,
             disk_number: data.read_u16(LittleEndian)?,
             disk_with_central_directory: data.read_u16(LittleEndian)?,
             number_of_central_directory_records_on_this_disk: data.read_u16(LittleEndian)?,
@@ -224,12 +258,149 @@ fn find_eocd<T: Read + Seek>(data: &mut BufReader<T>) -> Result<EndOfCentralDire
     }
 }
 
-/// Using an eocd, parse the central directory.
+/// Size of the Zip64 end of central directory locator, including the signature.
+const EOCD64_LOCATOR_LEN: u64 = 20;
+
+/// Look for the Zip64 end of central directory locator right before the eocd
+/// at `eocd_offset`, returns `None` if the archive is not a Zip64 archive.
+fn find_eocd64_locator<T: Read + Seek>(
+    data: &mut BufReader<T>,
+    eocd_offset: u64,
+) -> Result<Option<EndOfCentralDirectory64Locator>> {
+    if eocd_offset < EOCD64_LOCATOR_LEN {
+        return Ok(None);
+    }
+    let offset = eocd_offset - EOCD64_LOCATOR_LEN;
+    data.seek(SeekFrom::Start(offset))?;
+    if data.read_u32(LittleEndian)? != EOCD64_LOCATOR_SIG {
+        return Ok(None);
+    }
+
+    Ok(Some(EndOfCentralDirectory64Locator {
+        offset,
+        disk_with_end_of_central_directory64: data.read_u32(LittleEndian)?,
+        offset_of_end_of_central_directory64: data.read_u64(LittleEndian)?,
+        total_number_of_disks: data.read_u32(LittleEndian)?,
+    }))
+}
+
+/// Size of the Zip64 end of central directory record, without the extensible
+/// data sector.
+const EOCD64_LEN: u64 = 56;
+
+/// Parse the Zip64 end of central directory record pointed to by `locator`.
+fn parse_eocd64<T: Read + Seek>(
+    data: &mut BufReader<T>,
+    locator: &EndOfCentralDirectory64Locator,
+) -> Result<EndOfCentralDirectory64> {
+    let mut offset = locator.offset_of_end_of_central_directory64;
+    data.seek(SeekFrom::Start(offset))?;
+    let mut sig_candidate = data.read_u32(LittleEndian)?;
+    // Data prepended to the archive moves the record away from where the
+    // locator says it is, it's usually right before the locator though.
+    if sig_candidate != EOCD64_SIG && locator.offset >= EOCD64_LEN {
+        offset = locator.offset - EOCD64_LEN;
+        data.seek(SeekFrom::Start(offset))?;
+        sig_candidate = data.read_u32(LittleEndian)?;
+    }
+    if sig_candidate != EOCD64_SIG {
+        return Err(ZipError::InvalidSignature(sig_candidate));
+    }
+
+    let size_of_end_of_central_directory = data.read_u64(LittleEndian)?;
+    let version_made_by = data.read_u16(LittleEndian)?;
+    let version_needed_to_extract = data.read_u16(LittleEndian)?;
+    let disk_number = data.read_u32(LittleEndian)?;
+    let first_disk = data.read_u32(LittleEndian)?;
+    let number_of_central_directory_records_on_this_disk = data.read_u64(LittleEndian)?;
+    let total_number_of_central_directory_records = data.read_u64(LittleEndian)?;
+    let size_of_central_directory = data.read_u64(LittleEndian)?;
+    let offset_of_start_of_central_directory = data.read_u64(LittleEndian)?;
+    // The fixed part of the record is 44 bytes long, anything after it is the
+    // extensible data sector. The size doesn't count the signature and itself,
+    // and the whole record must end before the locator.
+    let record_end = offset
+        .checked_add(12)
+        .and_then(|start| start.checked_add(size_of_end_of_central_directory));
+    if record_end.is_none_or(|end| end > locator.offset) {
+        return Err(ZipError::InvalidEntry(offset));
+    }
+    let extensible_data_sector = {
+        let len = size_of_end_of_central_directory
+            .checked_sub(44)
+            .ok_or(ZipError::InvalidEntry(offset))?;
+        let mut buf = vec![0u8; len as usize];
+        data.read_exact(&mut buf)?;
+        buf
+    };
+
+    Ok(EndOfCentralDirectory64 {
+        offset,
+        size_of_end_of_central_directory,
+        version_made_by,
+        version_needed_to_extract,
+        disk_number,
+        first_disk,
+        number_of_central_directory_records_on_this_disk,
+        total_number_of_central_directory_records,
+        size_of_central_directory,
+        offset_of_start_of_central_directory,
+        extensible_data_sector,
+    })
+}
+
+/// Replace the saturated header values with the ones in the Zip64 extended
+/// information extra field.
+///
+/// The extra field only holds the values that overflowed in the header, in the
+/// order uncompressed size, compressed size and local header offset, so the
+/// values have to be consumed in that order.
+fn apply_zip64_extra(
+    extra: &[u8],
+    uncompressed_size: &mut u64,
+    compressed_size: &mut u64,
+    local_header_rel_offset: Option<&mut u64>,
+    disk_number_start: Option<&mut u32>,
+) {
+    let Some((values, disk_number)) = ExtraFields::new(extra).find_map(|field| match field {
+        ExtraField::Zip64 {
+            values,
+            disk_number,
+        } => Some((values, disk_number)),
+        _ => None,
+    }) else {
+        return;
+    };
+    let mut values = values.into_iter();
+    let mut next_u64 = |value: &mut u64| {
+        if *value == 0xFFFFFFFF {
+            if let Some(wide) = values.next() {
+                *value = wide;
+            }
+        }
+    };
+
+    next_u64(uncompressed_size);
+    next_u64(compressed_size);
+    if let Some(offset) = local_header_rel_offset {
+        next_u64(offset);
+    }
+    if let (Some(disk), Some(wide)) = (disk_number_start, disk_number) {
+        if *disk == 0xFFFF {
+            *disk = wide;
+        }
+    }
+}
+
+/// Parse the central directory record at `offset`, the reader must already be
+/// positioned there, this avoids throwing away the read buffer for every record.
+///
+/// Names not flagged as UTF-8 are decoded with `legacy`.
 fn parse_central_dir<T: Read + Seek>(
     data: &mut BufReader<T>,
     offset: u64,
+    legacy: &dyn LegacyDecoder,
 ) -> Result<CentralDirectory> {
-    data.seek(SeekFrom::Start(offset))?;
     let sig_candidate = data.read_u32(LittleEndian)?;
     if sig_candidate != CD_SIG {
         return Err(ZipError::InvalidSignature(sig_candidate));
@@ -242,33 +413,51 @@ fn parse_central_dir<T: Read + Seek>(
     let last_mod_time = data.read_u16(LittleEndian)?;
     let last_mod_date = data.read_u16(LittleEndian)?;
     let crc32 = data.read_u32(LittleEndian)?;
-    let compressed_size = data.read_u32(LittleEndian)?;
-    let uncompressed_size = data.read_u32(LittleEndian)?;
+    let mut compressed_size = data.read_u32(LittleEndian)? as u64;
+    let mut uncompressed_size = data.read_u32(LittleEndian)? as u64;
     // The lengths are stored here but the data is at the end of the structure.
     let fname_len = data.read_u16(LittleEndian)? as usize;
     let extra_len = data.read_u16(LittleEndian)? as usize;
     let comment_len = data.read_u16(LittleEndian)? as usize;
-    let disk_number_start = data.read_u16(LittleEndian)?;
+    let mut disk_number_start = data.read_u16(LittleEndian)? as u32;
     let internal_file_attributes = data.read_u16(LittleEndian)?;
     let external_file_attributes = data.read_u32(LittleEndian)?;
-    let relative_offset_of_local_header = data.read_u32(LittleEndian)?;
-    let filename = {
+    let mut relative_offset_of_local_header = data.read_u32(LittleEndian)? as u64;
+    let raw_filename = {
         let mut buf = vec![0u8; fname_len];
         data.read_exact(&mut buf)?;
-        PathBuf::from(String::from_utf8(buf)?)
+        buf
     };
     let extra_field = {
         let mut buf = vec![0u8; extra_len];
         data.read_exact(&mut buf)?;
         buf
     };
+    let (filename, name_source) = decode_with_extra(
+        &raw_filename,
+        flags,
+        &extra_field,
+        UNICODE_PATH_EXTRA_ID,
+        legacy,
+    );
+    let filename = PathBuf::from(filename);
     let file_comment = {
         let mut buf = vec![0u8; comment_len];
         data.read_exact(&mut buf)?;
         buf
     };
+    apply_zip64_extra(
+        &extra_field,
+        &mut uncompressed_size,
+        &mut compressed_size,
+        Some(&mut relative_offset_of_local_header),
+        Some(&mut disk_number_start),
+    );
     let len = data.stream_position()? - offset;
-    let is_directory = uncompressed_size == 0;
+    // Directories are named with a trailing slash, but some tools only set the
+    // directory attribute. An empty entry is not a directory by itself.
+    let is_directory = raw_filename.ends_with(b"/")
+        || attributes::file_type(version_made_by, external_file_attributes) == FileType::Directory;
 
     Ok(CentralDirectory {
         offset,
@@ -282,6 +471,8 @@ fn parse_central_dir<T: Read + Seek>(
         compressed_size,
         uncompressed_size,
         filename,
+        raw_filename,
+        name_source,
         extra_field,
         file_comment,
         disk_number_start,
@@ -293,6 +484,19 @@ fn parse_central_dir<T: Read + Seek>(
     })
 }
 
+/// Make sure `name` stays under the destination once joined onto it, names
+/// like `../../etc/passwd` or `/etc/passwd` would be written outside of it.
+fn check_extract_path(name: &Path) -> Result<()> {
+    let escapes = name.is_absolute()
+        || name
+            .components()
+            .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir));
+    if escapes {
+        return Err(ZipError::UnsafePath(name.to_path_buf()));
+    }
+    Ok(())
+}
+
 /// Parse a local file header.
 /// the offset is relative to the start of the file.
 fn parse_header<T: Read + Seek>(data: &mut BufReader<T>, offset: u64) -> Result<LocalFileHeader> {
@@ -311,84 +515,335 @@ fn parse_header<T: Read + Seek>(data: &mut BufReader<T>, offset: u64) -> Result<
     };
 
     if sig_candidate == LFH_SIG {
-        let offset = data.stream_position()? - 4;
-        let version = data.read_u16(LittleEndian)?;
-        let flags = data.read_u16(LittleEndian)?;
-        let compression = data.read_u16(LittleEndian)?;
-        let last_mod_time = data.read_u16(LittleEndian)?;
-        let last_mod_date = data.read_u16(LittleEndian)?;
-        let mut crc32 = 0;
-        let mut compressed_size = 0;
-        let mut uncompressed_size = 0;
-        // Do we need to look for the data descriptor?
-        if flags & 1 << 3 == 0 {
-            crc32 = data.read_u32(LittleEndian)?;
-            compressed_size = data.read_u32(LittleEndian)?;
-            uncompressed_size = data.read_u32(LittleEndian)?;
-        }
-        let fname_len = data.read_u16(LittleEndian)? as usize;
-        let extra_len = data.read_u16(LittleEndian)? as usize;
-        let filename = {
-            let mut buf = vec![0u8; fname_len];
-            data.read_exact(&mut buf)?;
-            PathBuf::from(String::from_utf8(buf)?)
-        };
-        let extra_field = {
-            let mut buf = vec![0u8; extra_len];
-            data.read_exact(&mut buf)?;
-            buf
-        };
-        let data_offset = data.stream_position()?;
-
-        Ok(LocalFileHeader {
-            offset,
-            version,
-            flags,
-            compression,
-            last_mod_time,
-            last_mod_date,
-            crc32,
-            compressed_size,
-            uncompressed_size,
-            filename,
-            extra_field,
-            data_offset,
-        })
+        read_local_header(data, offset, &Cp437)
     } else {
         Err(ZipError::InvalidSignature(sig_candidate))
     }
 }
 
+/// Read the rest of a local file header at `offset` whose signature has
+/// already been consumed, this only needs [`Read`] so it's shared with the
+/// forward only [`crate::stream_reader::ZipStreamReader`].
+pub(crate) fn read_local_header<T: Read>(
+    data: &mut T,
+    offset: u64,
+    legacy: &dyn LegacyDecoder,
+) -> Result<LocalFileHeader> {
+    let version = data.read_u16(LittleEndian)?;
+    let flags = data.read_u16(LittleEndian)?;
+    let compression = data.read_u16(LittleEndian)?;
+    let last_mod_time = data.read_u16(LittleEndian)?;
+    let last_mod_date = data.read_u16(LittleEndian)?;
+    // When bit 3 of the flags is set these are zero and the real values
+    // are in the data descriptor that follows the file data.
+    let crc32 = data.read_u32(LittleEndian)?;
+    let mut compressed_size = data.read_u32(LittleEndian)? as u64;
+    let mut uncompressed_size = data.read_u32(LittleEndian)? as u64;
+    let fname_len = data.read_u16(LittleEndian)? as usize;
+    let extra_len = data.read_u16(LittleEndian)? as usize;
+    let raw_filename = {
+        let mut buf = vec![0u8; fname_len];
+        data.read_exact(&mut buf)?;
+        buf
+    };
+    let extra_field = {
+        let mut buf = vec![0u8; extra_len];
+        data.read_exact(&mut buf)?;
+        buf
+    };
+    let (filename, name_source) = decode_with_extra(
+        &raw_filename,
+        flags,
+        &extra_field,
+        UNICODE_PATH_EXTRA_ID,
+        legacy,
+    );
+    let filename = PathBuf::from(filename);
+    apply_zip64_extra(
+        &extra_field,
+        &mut uncompressed_size,
+        &mut compressed_size,
+        None,
+        None,
+    );
+    let data_offset = offset + 30 + fname_len as u64 + extra_len as u64;
+
+    Ok(LocalFileHeader {
+        offset,
+        version,
+        flags,
+        compression,
+        last_mod_time,
+        last_mod_date,
+        crc32,
+        compressed_size,
+        uncompressed_size,
+        filename,
+        raw_filename,
+        name_source,
+        extra_field,
+        data_offset,
+    })
+}
+
+/// Parse a data descriptor, the reader must be positioned right after the
+/// file data at `offset`.
+///
+/// The signature is optional so the first field is taken to be the signature
+/// only if it matches it, `zip64` selects the 8 byte size fields.
+pub(crate) fn parse_data_descriptor<T: Read>(
+    data: &mut T,
+    offset: u64,
+    zip64: bool,
+) -> Result<DataDescriptor> {
+    let mut len = 0;
+    let mut crc32 = data.read_u32(LittleEndian)?;
+    if crc32 == DD_SIG {
+        crc32 = data.read_u32(LittleEndian)?;
+        len += 4;
+    }
+    let (compressed_size, uncompressed_size) = if zip64 {
+        len += 20;
+        (data.read_u64(LittleEndian)?, data.read_u64(LittleEndian)?)
+    } else {
+        len += 12;
+        (
+            data.read_u32(LittleEndian)? as u64,
+            data.read_u32(LittleEndian)? as u64,
+        )
+    };
+
+    Ok(DataDescriptor {
+        offset,
+        crc32,
+        compressed_size,
+        uncompressed_size,
+        len,
+    })
+}
+
+/// Length of a central directory record without its name, extra field and
+/// comment.
+const CD_FIXED_LEN: u64 = 46;
+
+/// Find where `disk` starts in the stream, see [`index_archive`].
+fn volume_start(volume_starts: &[u64], disk: u32) -> Result<u64> {
+    match volume_starts {
+        [archive_offset] => Ok(*archive_offset),
+        _ => volume_starts
+            .get(disk as usize)
+            .copied()
+            .ok_or(ZipError::MissingVolume(disk)),
+    }
+}
+
+/// Index the central directory by reading exactly `entries` records back to
+/// back, starting at `cd_offset`, the total length of the records must add up
+/// to `cd_size`. Every record is kept, even when names repeat.
+///
+/// `volume_starts` holds where each disk of a multi-volume archive starts in
+/// the stream. For single disk archives it only holds where the archive starts,
+/// which is not 0 when something was prepended to it. The local header offsets
+/// are adjusted with it so they point at the right place in the stream.
+///
+/// Names not flagged as UTF-8 are decoded with `legacy`, [`Cp437`] per the spec.
 pub fn index_archive<R: Read + Seek>(
     reader: &mut BufReader<R>,
-    hint: Option<u64>,
+    cd_offset: u64,
+    entries: u64,
+    cd_size: u64,
+    volume_starts: &[u64],
+    legacy: &dyn LegacyDecoder,
 ) -> Result<ZipIndex> {
-    let mut index = BTreeMap::new();
-    let mut hint = hint.unwrap_or(0);
-
-    reader.rewind()?;
-
-    loop {
-        let offset = match find_next_signature(reader, CD_SIG, Some(hint)) {
-            Ok(offset) => offset,
-            Err(e) => {
-                return if e
-                    == ZipError::IOError(std::io::Error::from(std::io::ErrorKind::UnexpectedEof))
-                {
-                    Ok(ZipIndex::new(index))
-                } else {
-                    Err(e)
-                };
+    let mut index = Vec::new();
+    read_central_dir(
+        reader,
+        cd_offset,
+        entries,
+        cd_size,
+        volume_starts,
+        legacy,
+        |header| index.push(header),
+    )?;
+    Ok(ZipIndex::new(index))
+}
+
+/// Index the central directory like [`index_archive`], into a
+/// [`CompactIndex`] that only keeps the fixed-size part of every record.
+pub fn index_archive_compact<R: Read + Seek>(
+    reader: &mut BufReader<R>,
+    cd_offset: u64,
+    entries: u64,
+    cd_size: u64,
+    volume_starts: &[u64],
+    legacy: &dyn LegacyDecoder,
+) -> Result<CompactIndex> {
+    let mut index = CompactIndex::default();
+    read_central_dir(
+        reader,
+        cd_offset,
+        entries,
+        cd_size,
+        volume_starts,
+        legacy,
+        |header| {
+            // The count has been checked against the size by now.
+            if index.entries().is_empty() {
+                index.reserve(entries as usize);
             }
-        };
+            index.push(&header);
+        },
+    )?;
+    index.sort();
+    Ok(index)
+}
+
+/// Check that the central directory fits in the stream and is long enough for
+/// `entries` records, before trusting the count to walk or allocate anything.
+fn check_central_dir_bounds<R: Read + Seek>(
+    reader: &mut BufReader<R>,
+    cd_offset: u64,
+    entries: u64,
+    cd_size: u64,
+) -> Result<()> {
+    let available = reader.seek(SeekFrom::End(0))?.saturating_sub(cd_offset);
+    if cd_size > available {
+        return Err(ZipError::CentralDirectorySizeMismatch(cd_size, available));
+    }
+    let min_size = entries.saturating_mul(CD_FIXED_LEN);
+    if min_size > cd_size {
+        return Err(ZipError::CentralDirectorySizeMismatch(cd_size, min_size));
+    }
+    Ok(())
+}
+
+/// Parse the records of the central directory one at a time and hand them
+/// to `each`, see [`index_archive`].
+fn read_central_dir<R: Read + Seek>(
+    reader: &mut BufReader<R>,
+    cd_offset: u64,
+    entries: u64,
+    cd_size: u64,
+    volume_starts: &[u64],
+    legacy: &dyn LegacyDecoder,
+    mut each: impl FnMut(CentralDirectory),
+) -> Result<()> {
+    check_central_dir_bounds(reader, cd_offset, entries, cd_size)?;
+    let mut offset = cd_offset;
 
-        let header = parse_central_dir(reader, offset)?;
-        hint += header.len;
-        index.insert(header.filename.clone(), header);
+    reader.seek(SeekFrom::Start(cd_offset))?;
+    for _ in 0..entries {
+        let mut header = parse_central_dir(reader, offset, legacy)?;
+        header.local_header_rel_offset += volume_start(volume_starts, header.disk_number_start)?;
+        offset += header.len;
+        each(header);
     }
+
+    if offset - cd_offset != cd_size {
+        return Err(ZipError::CentralDirectorySizeMismatch(
+            cd_size,
+            offset - cd_offset,
+        ));
+    }
+    Ok(())
+}
+
+/// Parse again the whole central directory record of an entry of a
+/// [`CompactIndex`], with its extra field and comment.
+pub fn load_central_dir<R: Read + Seek>(
+    reader: &mut BufReader<R>,
+    entry: &CompactEntry,
+    legacy: &dyn LegacyDecoder,
+) -> Result<CentralDirectory> {
+    reader.seek(SeekFrom::Start(entry.cd_offset))?;
+    let mut header = parse_central_dir(reader, entry.cd_offset, legacy)?;
+    // The offset in the record is relative to its volume.
+    header.local_header_rel_offset = entry.local_header_offset;
+    Ok(header)
+}
+
+/// Where the central directory of an archive is, from its end of central
+/// directory records.
+pub(crate) struct CentralDirLocation {
+    pub eocd: EndOfCentralDirectory,
+    pub eocd64: Option<EndOfCentralDirectory64>,
+    /// Where each disk starts in the stream, see [`index_archive`].
+    pub volume_starts: Vec<u64>,
+    /// The offset of the first record in the stream.
+    pub cd_offset: u64,
+    pub entries: u64,
+    pub cd_size: u64,
+}
+
+/// Find the end of central directory records and where the central
+/// directory starts, `volume_starts` holds where each disk of a multi-volume
+/// archive starts in `reader`.
+pub(crate) fn locate_central_dir<R: Read + Seek>(
+    reader: &mut BufReader<R>,
+    volume_starts: Option<Vec<u64>>,
+) -> Result<CentralDirLocation> {
+    let eocd = find_eocd(reader)?;
+    let eocd64 = match find_eocd64_locator(reader, eocd.offset)? {
+        Some(mut locator) => {
+            if let Some(volume_starts) = &volume_starts {
+                locator.offset_of_end_of_central_directory64 +=
+                    volume_start(volume_starts, locator.disk_with_end_of_central_directory64)?;
+            }
+            Some(parse_eocd64(reader, &locator)?)
+        }
+        None => None,
+    };
+    let (cd_disk, cd_offset, entries, cd_size) = match &eocd64 {
+        Some(eocd64) => (
+            eocd64.first_disk,
+            eocd64.offset_of_start_of_central_directory,
+            eocd64.total_number_of_central_directory_records,
+            eocd64.size_of_central_directory,
+        ),
+        None => (
+            eocd.disk_with_central_directory as u32,
+            eocd.offset_of_start_of_central_directory as u64,
+            eocd.total_number_of_central_directory_records as u64,
+            eocd.size_of_central_directory as u64,
+        ),
+    };
+    let volume_starts = volume_starts.unwrap_or_else(|| {
+        // The central directory ends right where the eocd starts, if it's
+        // not where the archive says it starts something was prepended to
+        // it, like the stub of a self-extracting archive, and all offsets
+        // are off by that.
+        let cd_end = eocd64.as_ref().map_or(eocd.offset, |eocd64| eocd64.offset);
+        let archive_offset = cd_end
+            .checked_sub(cd_size)
+            .and_then(|cd_start| cd_start.checked_sub(cd_offset))
+            .unwrap_or(0);
+        vec![archive_offset]
+    });
+    Ok(CentralDirLocation {
+        cd_offset: volume_start(&volume_starts, cd_disk)? + cd_offset,
+        eocd,
+        eocd64,
+        volume_starts,
+        entries,
+        cd_size,
+    })
+}
+
+/// Write `index` to the sidecar file at `path`, see [`write_index_cache`].
+fn save_index<P: AsRef<Path>>(
+    path: P,
+    index: &ZipIndex,
+    fingerprint: &ArchiveFingerprint,
+) -> Result<()> {
+    write_cache_file(path.as_ref(), |writer| {
+        write_index_cache(writer, index, fingerprint)
+    })
 }
 
 /// Dump the file as it's stored in the zip file.
+///
+/// The compressed size is not trusted to allocate, an entry claiming more
+/// data than the stream holds fails once the stream ends.
 pub fn dump_file<T: Read + Seek>(
     data: &mut BufReader<T>,
     CentralDirectory {
@@ -397,24 +852,138 @@ pub fn dump_file<T: Read + Seek>(
         ..
     }: &CentralDirectory,
 ) -> Result<Vec<u8>> {
-    let mut buf = vec![0u8; *compressed_size as usize];
-    data.seek(SeekFrom::Start(*local_header_rel_offset as u64))?;
-    let header = parse_header(data, *local_header_rel_offset as u64)?;
+    data.seek(SeekFrom::Start(*local_header_rel_offset))?;
+    let header = parse_header(data, *local_header_rel_offset)?;
     data.seek(SeekFrom::Start(header.data_offset))?;
-    data.read_exact(&mut buf)?;
+    let mut buf = Vec::new();
+    data.take(*compressed_size).read_to_end(&mut buf)?;
+    if (buf.len() as u64) < *compressed_size {
+        return Err(ZipError::IOError(std::io::ErrorKind::UnexpectedEof.into()));
+    }
     Ok(buf)
 }
 
 /// Get the local file header for a file from a central directory entry.
+///
+/// If the entry was written with a data descriptor, the crc32 and sizes are
+/// filled in from it, using the compressed size in `cd` to find it.
 pub fn get_local_file_header<T: Read + Seek>(
     data: &mut BufReader<T>,
     CentralDirectory {
         local_header_rel_offset: relative_offset_of_local_header,
+        compressed_size,
         ..
     }: &CentralDirectory,
 ) -> Result<LocalFileHeader> {
-    data.seek(SeekFrom::Start(*relative_offset_of_local_header as u64))?;
-    parse_header(data, *relative_offset_of_local_header as u64)
+    data.seek(SeekFrom::Start(*relative_offset_of_local_header))?;
+    let mut header = parse_header(data, *relative_offset_of_local_header)?;
+    if header.flags & 1 << 3 != 0 {
+        let offset = header.data_offset + compressed_size;
+        let zip64 = header
+            .extra_fields()
+            .any(|field| matches!(field, ExtraField::Zip64 { .. }));
+        data.seek(SeekFrom::Start(offset))?;
+        let descriptor = parse_data_descriptor(data, offset, zip64)?;
+        header.crc32 = descriptor.crc32;
+        header.compressed_size = descriptor.compressed_size;
+        header.uncompressed_size = descriptor.uncompressed_size;
+    }
+    Ok(header)
+}
+
+/// Reader over the expanded contents of a single entry, created by
+/// [`ZipReader::open_entry`].
+///
+/// Only the compressed range of the entry is ever read from the archive, and
+/// the data is expanded as it's read, so entries of any size can be copied
+/// with [`std::io::copy`].
+///
+/// The CRC-32 of the data is computed as it goes, reaching the end of an entry
+/// that doesn't match the checksum in the archive fails with
+/// [`ZipError::CrcMismatch`] wrapped in an [`std::io::Error`].
+pub struct ZipEntryReader<'a> {
+    inner: Box<dyn Read + 'a>,
+    crc_check: Option<CrcCheck>,
+}
+
+struct CrcCheck {
+    name: PathBuf,
+    /// Shared with whoever finds out the checksum, it's only known after the
+    /// data has been read for entries with a data descriptor in a stream.
+    expected: Rc<Cell<Option<u32>>>,
+    digest: Digest<'static, u32>,
+}
+
+impl<'a> ZipEntryReader<'a> {
+    /// Expand `inner` and check the result against the checksum in `expected`,
+    /// nothing is checked if it's still unknown when the data runs out.
+    pub(crate) fn new(
+        inner: Box<dyn Read + 'a>,
+        name: PathBuf,
+        expected: Rc<Cell<Option<u32>>>,
+    ) -> Self {
+        ZipEntryReader {
+            inner,
+            crc_check: Some(CrcCheck {
+                name,
+                expected,
+                digest: ZIP_CRC.digest(),
+            }),
+        }
+    }
+
+    /// Turn the CRC-32 verification on or off, it's on by default.
+    pub fn verify_crc(mut self, verify: bool) -> Self {
+        if !verify {
+            self.crc_check = None;
+        }
+        self
+    }
+}
+
+impl Read for ZipEntryReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if read > 0 || buf.is_empty() {
+            if let Some(check) = &mut self.crc_check {
+                check.digest.update(&buf[..read]);
+            }
+        } else if let Some(check) = self.crc_check.take() {
+            let actual = check.digest.finalize();
+            match check.expected.get() {
+                Some(expected) if expected != actual => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        ZipError::CrcMismatch(check.name, expected, actual),
+                    ));
+                }
+                _ => {}
+            }
+        }
+        Ok(read)
+    }
+}
+
+/// Open a reader over the expanded contents of the entry described by `cd`.
+pub fn open_entry<'a, T: Read + Seek>(
+    data: &'a mut BufReader<T>,
+    cd: &CentralDirectory,
+    codec: &impl CompressionCodec,
+) -> Result<ZipEntryReader<'a>> {
+    if cd.compression != codec.int_id() {
+        return Err(ZipError::MismatchedCompressionMethod(
+            cd.compression,
+            codec.int_id(),
+        ));
+    }
+    let header = parse_header(data, cd.local_header_rel_offset)?;
+    data.seek(SeekFrom::Start(header.data_offset))?;
+    let raw = Read::take(data, cd.compressed_size);
+    Ok(ZipEntryReader::new(
+        codec.expanding_reader(Box::new(raw))?,
+        cd.filename.clone(),
+        Rc::new(Cell::new(Some(cd.crc32))),
+    ))
 }
 
 /// Extract a file from `reader` to `where_to` using `codec` and the info in `cd`.
@@ -424,12 +993,27 @@ pub fn extract_file<R, P>(
     where_to: P,
     codec: &mut impl CompressionCodec,
 ) -> Result<()>
+where
+    R: Read + Seek,
+    P: AsRef<Path>,
+{
+    extract_entry(reader, cd, where_to, codec, true)
+}
+
+fn extract_entry<R, P>(
+    reader: &mut R,
+    cd: &CentralDirectory,
+    where_to: P,
+    codec: &impl CompressionCodec,
+    verify_crc: bool,
+) -> Result<()>
 where
     R: Read + Seek,
     P: AsRef<Path>,
 {
     let mut reader = BufReader::new(reader);
     let where_to = where_to.as_ref();
+    check_extract_path(&cd.filename)?;
     let dest_path = where_to.join(&cd.filename);
     if !where_to.exists() {
         return Err(ZipError::IOError(std::io::Error::new(
@@ -445,37 +1029,224 @@ where
         )));
     }
 
+    if let Some(parent) = dest_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut entry = open_entry(&mut reader, cd, codec)?.verify_crc(verify_crc);
     let mut file = File::create(&dest_path)?;
-
-    if codec.int_id() != cd.compression {
-        return Err(ZipError::MismatchedCompressionMethod(
-            cd.compression,
-            codec.int_id(),
-        ));
+    if let Err(e) = std::io::copy(&mut entry, &mut file) {
+        // Don't leave a truncated or corrupted file behind.
+        drop(file);
+        let _ = std::fs::remove_file(&dest_path);
+        return Err(e.into());
     }
-    codec.streamed_expansion(&mut reader, &mut file);
     Ok(())
 }
 
+impl<R: Read + Seek> ZipReader<MultiVolumeReader<R>> {
+    /// Read and index an archive split across several volumes, the parts
+    /// must be in order with the part holding the central directory last.
+    pub fn new_multi_volume(parts: Vec<R>) -> Result<Self> {
+        let reader = MultiVolumeReader::new(parts)?;
+        let volume_starts = reader.volume_starts().to_vec();
+        Self::open(reader, Some(volume_starts))
+    }
+}
+
+impl ZipReader<MultiVolumeReader<File>> {
+    /// Open a split archive from the path of its last part, see
+    /// [`MultiVolumeReader::open`].
+    pub fn open_split<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let reader = MultiVolumeReader::open(path)?;
+        let volume_starts = reader.volume_starts().to_vec();
+        Self::open(reader, Some(volume_starts))
+    }
+}
+
 impl<R: Read + Seek> ZipReader<R> {
     /// Read and index a ZIP archive.
     pub fn new(reader: R) -> Result<ZipReader<R>> {
+        Self::open(reader, None)
+    }
+
+    /// Read and index an archive, `volume_starts` holds where each disk of a
+    /// multi-volume archive starts in `reader`.
+    fn open(reader: R, volume_starts: Option<Vec<u64>>) -> Result<ZipReader<R>> {
         let mut reader = BufReader::new(reader);
-        let eocd = find_eocd(&mut reader)?;
+        let location = locate_central_dir(&mut reader, volume_starts)?;
         let index = index_archive(
             &mut reader,
-            Some(eocd.offset_of_start_of_central_directory as u64),
+            location.cd_offset,
+            location.entries,
+            location.cd_size,
+            &location.volume_starts,
+            &Cp437,
         )?;
+        Ok(Self::from_index(reader, location, index, Cp437))
+    }
+
+    /// Read an archive with the index saved by [`ZipReader::save_index`] in
+    /// the sidecar file at `cache`, if it was made for this archive. The
+    /// archive is indexed and the sidecar written again otherwise, which
+    /// includes missing or damaged sidecars. Failing to write the sidecar
+    /// doesn't fail the open, the error is kept in
+    /// [`ZipReader::cache_error`] and writing is tried again next time.
+    ///
+    /// Checking the sidecar reads the central directory but doesn't parse it.
+    pub fn new_cached<P: AsRef<Path>>(reader: R, cache: P) -> Result<ZipReader<R>> {
+        Self::new_cached_with_legacy_decoder(reader, cache, Cp437)
+    }
+
+    /// Like [`ZipReader::new_cached`], decoding the names that are not flagged
+    /// as UTF-8 with `decoder`, see [`ZipReader::set_legacy_decoder`]. The
+    /// sidecar holds the names as they were decoded when it was written, so it
+    /// must be read with the decoder it was written with.
+    pub fn new_cached_with_legacy_decoder<P: AsRef<Path>>(
+        reader: R,
+        cache: P,
+        decoder: impl LegacyDecoder + 'static,
+    ) -> Result<ZipReader<R>> {
+        let mut reader = BufReader::new(reader);
+        let location = locate_central_dir(&mut reader, None)?;
+        let fingerprint = ArchiveFingerprint::compute(
+            &mut reader,
+            location.eocd.offset,
+            location.cd_offset,
+            location.cd_size,
+        )?;
+        let cached = File::open(cache.as_ref())
+            .map_err(ZipError::from)
+            .and_then(|file| read_index_cache(&mut BufReader::new(file), &fingerprint));
+        let mut cache_error = None;
+        let index = match cached {
+            Ok(Some(index)) => index,
+            _ => {
+                let index = index_archive(
+                    &mut reader,
+                    location.cd_offset,
+                    location.entries,
+                    location.cd_size,
+                    &location.volume_starts,
+                    &decoder,
+                )?;
+                cache_error = save_index(cache, &index, &fingerprint).err();
+                index
+            }
+        };
+        let mut zip = Self::from_index(reader, location, index, decoder);
+        zip.cache_error = cache_error;
+        Ok(zip)
+    }
+
+    fn from_index(
+        reader: BufReader<R>,
+        location: CentralDirLocation,
+        index: ZipIndex,
+        decoder: impl LegacyDecoder + 'static,
+    ) -> ZipReader<R> {
+        ZipReader {
+            reader,
+            index,
+            archive_offset: location.volume_starts[0],
+            eocd: location.eocd,
+            eocd64: location.eocd64,
+            central_dir: location.cd_offset..location.cd_offset + location.cd_size,
+            verify_crc: true,
+            legacy_decoder: Arc::new(decoder),
+            time_zone: TimeZone::default(),
+            cache_error: None,
+        }
+    }
+
+    /// What ties the index to this archive, see [`ZipReader::save_index`].
+    pub fn fingerprint(&mut self) -> Result<ArchiveFingerprint> {
+        ArchiveFingerprint::compute(
+            &mut self.reader,
+            self.eocd.offset,
+            self.central_dir.start,
+            self.central_dir.end - self.central_dir.start,
+        )
+    }
+
+    /// Save the index to the sidecar file at `path`, to reopen the archive
+    /// with [`ZipReader::new_cached`] without parsing the central directory.
+    ///
+    /// The names are saved as they are decoded now, calling
+    /// [`ZipReader::set_legacy_decoder`] after reopening decodes them again.
+    /// The duplicate policy and lookup mode are not saved, they must be set
+    /// again after reopening.
+    pub fn save_index<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let fingerprint = self.fingerprint()?;
+        save_index(path, &self.index, &fingerprint)
+    }
+
+    /// Why [`ZipReader::new_cached`] couldn't write the sidecar, `None` if it
+    /// was written or read.
+    pub fn cache_error(&self) -> Option<&ZipError> {
+        self.cache_error.as_ref()
+    }
+
+    /// Whether the archive uses the Zip64 end of central directory record.
+    pub fn is_zip64(&self) -> bool {
+        self.eocd64.is_some()
+    }
+
+    /// The end of central directory record of the archive.
+    pub fn end_of_central_directory(&self) -> &EndOfCentralDirectory {
+        &self.eocd
+    }
+
+    /// The Zip64 end of central directory record, for Zip64 archives.
+    pub fn end_of_central_directory64(&self) -> Option<&EndOfCentralDirectory64> {
+        self.eocd64.as_ref()
+    }
+
+    /// The archive comment as stored in the archive.
+    pub fn archive_comment_raw(&self) -> &[u8] {
+        &self.eocd.zip_file_comment
+    }
+
+    /// The archive comment, `None` if there's none.
+    ///
+    /// The end of central directory has no flags to say the comment is UTF-8,
+    /// so it's taken to be UTF-8 when every entry is flagged as UTF-8, and is
+    /// decoded with the legacy decoder otherwise, like the names.
+    pub fn archive_comment(&self) -> Option<String> {
+        let comment = &self.eocd.zip_file_comment;
+        if comment.is_empty() {
+            return None;
+        }
+        let entries = self.index.entries();
+        let utf8 = !entries.is_empty() && entries.iter().all(|cd| cd.flags & UTF8_FLAG != 0);
+        let flags = if utf8 { UTF8_FLAG } else { 0 };
+        Some(decode_text(comment, flags, &*self.legacy_decoder))
+    }
+
+    /// The decoded comment of an entry, `None` if it has none. The Unicode
+    /// Comment extra field is preferred if it matches, see
+    /// [`decode_with_extra`].
+    pub fn entry_comment<T: AsRef<Path>>(&self, filename: &T) -> Result<Option<String>> {
+        let entry = self.index.find(filename.as_ref())?;
+        Ok(entry_comment(entry, &*self.legacy_decoder))
+    }
 
-        Ok(ZipReader { reader, index, is_zip64: false })
+    /// Length of the data in front of the archive, like the stub of a
+    /// self-extracting archive, the offsets in the index already account for it.
+    pub fn prefix_len(&self) -> u64 {
+        self.archive_offset
+    }
+
+    /// Read the data in front of the archive.
+    pub fn prefix(&mut self) -> Result<Vec<u8>> {
+        let mut buf = vec![0u8; self.archive_offset as usize];
+        self.reader.seek(SeekFrom::Start(0))?;
+        self.reader.read_exact(&mut buf)?;
+        Ok(buf)
     }
 
     /// Dump a file from the archive, without decompressing it.
     pub fn dump_file<T: AsRef<Path>>(&mut self, filename: &T) -> Result<Vec<u8>> {
-        let entry = self
-            .index
-            .get(filename.as_ref())
-            .ok_or(ZipError::EntryNotFound(filename.as_ref().into()))?;
+        let entry = self.index.find(filename.as_ref())?;
         dump_file(&mut self.reader, entry)
     }
 
@@ -484,17 +1255,107 @@ impl<R: Read + Seek> ZipReader<R> {
         dump_file(&mut self.reader, cd)
     }
 
+    /// Open a reader that expands the file with `codec` as it's read, without
+    /// loading the whole entry in memory.
+    pub fn open_entry<T: AsRef<Path>>(
+        &mut self,
+        filename: &T,
+        codec: &impl CompressionCodec,
+    ) -> Result<ZipEntryReader<'_>> {
+        let entry = self.index.find(filename.as_ref())?;
+        Ok(open_entry(&mut self.reader, entry, codec)?.verify_crc(self.verify_crc))
+    }
+
+    /// Open a reader that expands the file with `codec` as it's read, from a
+    /// central directory entry.
+    pub fn open_entry_from_cd(
+        &mut self,
+        cd: &CentralDirectory,
+        codec: &impl CompressionCodec,
+    ) -> Result<ZipEntryReader<'_>> {
+        Ok(open_entry(&mut self.reader, cd, codec)?.verify_crc(self.verify_crc))
+    }
+
+    /// Choose whether extracted data is checked against the CRC-32 stored in
+    /// the archive, this is on by default and can be turned off for speed.
+    pub fn set_verify_crc(&mut self, verify: bool) {
+        self.verify_crc = verify;
+    }
+
+    /// Decode the names that are not flagged as UTF-8 with `decoder` instead of
+    /// code page 437, the index is rebuilt with the new names. This is needed
+    /// for archives made by tools that use the local code page, like the
+    /// Shift-JIS or GBK archives made on Japanese or Chinese Windows.
+    pub fn set_legacy_decoder(&mut self, decoder: impl LegacyDecoder + 'static) {
+        self.legacy_decoder = Arc::new(decoder);
+        let policy = self.index.duplicate_policy();
+        let lookup_mode = self.index.lookup_mode();
+        let mut entries =
+            std::mem::replace(&mut self.index, ZipIndex::new(Vec::new())).into_entries();
+        for entry in entries.iter_mut() {
+            let (filename, name_source) = decode_with_extra(
+                &entry.raw_filename,
+                entry.flags,
+                &entry.extra_field,
+                UNICODE_PATH_EXTRA_ID,
+                &*self.legacy_decoder,
+            );
+            entry.filename = PathBuf::from(filename);
+            entry.name_source = name_source;
+        }
+        self.index = ZipIndex::new(entries);
+        self.index.set_duplicate_policy(policy);
+        self.index.set_lookup_mode(lookup_mode);
+    }
+
+    /// Choose the time zone the DOS timestamps of the entries are interpreted
+    /// in, this is the local time zone by default.
+    pub fn set_time_zone(&mut self, time_zone: TimeZone) {
+        self.time_zone = time_zone;
+    }
+
+    /// Choose which entry is used when several have the same name, the last
+    /// one by default, see [`DuplicatePolicy`].
+    pub fn set_duplicate_policy(&mut self, policy: DuplicatePolicy) {
+        self.index.set_duplicate_policy(policy);
+    }
+
+    /// Choose how the names given to the lookups are matched against the
+    /// names of the entries, exactly by default, see [`LookupMode`].
+    pub fn set_lookup_mode(&mut self, mode: LookupMode) {
+        self.index.set_lookup_mode(mode);
+    }
+
     /// Get the index of the archive.
     pub fn index(&self) -> &ZipIndex {
         &self.index
     }
 
     pub fn file_info<T: AsRef<Path>>(&self, filename: &T) -> Result<ZipEntryInfo> {
-        let entry = self
-            .index
-            .get(filename.as_ref())
-            .ok_or(ZipError::EntryNotFound(filename.as_ref().into()))?;
-        Ok(ZipEntryInfo::from_central_dir(entry))
+        let entry = self.index.find(filename.as_ref())?;
+        Ok(ZipEntryInfo::from_central_dir(
+            entry,
+            &*self.legacy_decoder,
+            self.time_zone,
+        ))
+    }
+
+    /// Like [`ZipReader::file_info`], also reading the extra fields of the
+    /// local header, whose extended timestamp field holds the access and
+    /// creation times left out of the central directory.
+    pub fn local_file_info<T: AsRef<Path>>(&mut self, filename: &T) -> Result<ZipEntryInfo> {
+        let entry = self.index.find(filename.as_ref())?;
+        let header = parse_header(&mut self.reader, entry.local_header_rel_offset)?;
+        let fields = entry
+            .extra_fields()
+            .chain(header.extra_fields())
+            .collect::<Vec<_>>();
+        Ok(ZipEntryInfo::from_extra_fields(
+            entry,
+            &fields,
+            &*self.legacy_decoder,
+            self.time_zone,
+        ))
     }
 
     /// Extract a file from the archive.
@@ -503,11 +1364,7 @@ impl<R: Read + Seek> ZipReader<R> {
         filename: &T,
         codec: &mut impl CompressionCodec,
     ) -> Result<Vec<u8>> {
-        let entry = self
-            .index
-            .get(filename.as_ref())
-            .ok_or(ZipError::EntryNotFound(filename.as_ref().into()))?
-            .clone();
+        let entry = self.index.find(filename.as_ref())?.clone();
         self.extract_data_from_cd(&entry, codec)
     }
 
@@ -518,14 +1375,10 @@ impl<R: Read + Seek> ZipReader<R> {
         cd: &CentralDirectory,
         codec: &mut impl CompressionCodec,
     ) -> Result<Vec<u8>> {
-        if cd.compression != codec.int_id() {
-            return Err(ZipError::MismatchedCompressionMethod(
-                cd.compression,
-                codec.int_id(),
-            ));
-        }
-        let data = dump_file(&mut self.reader, cd)?;
-        codec.expand((&data, data.len()))
+        let mut entry = open_entry(&mut self.reader, cd, codec)?.verify_crc(self.verify_crc);
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        Ok(data)
     }
 
     /// Extract all files to the given directory.
@@ -539,24 +1392,52 @@ impl<R: Read + Seek> ZipReader<R> {
             .files()
             .cloned()
             .collect::<Vec<CentralDirectory>>();
+        // Check every name before anything is written.
+        for entry in self.index.entries() {
+            check_extract_path(&entry.filename)?;
+        }
         self.build_directories(dir)?;
         for file in files {
-            extract_file(&mut self.reader, &file, dir, codec)?;
+            extract_entry(&mut self.reader, &file, dir, codec, self.verify_crc)?;
         }
 
         Ok(())
     }
 
-    fn build_directories<T: AsRef<Path>>(&mut self, base: &T) -> Result<()> {
-        let dirs = self
+    /// Extract the entries accepted by `filter` into `dir`, such as a
+    /// [`crate::glob::Glob`] or a [`crate::glob::PathPrefix`]. The parent
+    /// directories of the extracted files are created as needed.
+    pub fn extract_matching<T: AsRef<Path>>(
+        &mut self,
+        dir: &T,
+        codec: &mut impl CompressionCodec,
+        filter: &impl EntryFilter,
+    ) -> Result<()> {
+        let entries = self
             .index
-            .dirs()
-            .cloned()
+            .iter()
+            .filter(|(path, entry)| filter.matches(path, entry))
+            .map(|(_, entry)| entry.clone())
             .collect::<Vec<CentralDirectory>>();
-        for dir in dirs {
-            let mut path = base.as_ref().to_path_buf();
-            path.push(&dir.filename);
-            std::fs::create_dir_all(path)?;
+        for entry in &entries {
+            check_extract_path(&entry.filename)?;
+        }
+        for entry in entries {
+            if entry.is_directory {
+                std::fs::create_dir_all(dir.as_ref().join(&entry.filename))?;
+            } else {
+                extract_entry(&mut self.reader, &entry, dir, codec, self.verify_crc)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Create every directory of the archive under `base`, including the
+    /// ones that are only implied by the paths of the files.
+    fn build_directories<T: AsRef<Path>>(&mut self, base: &T) -> Result<()> {
+        for dir in self.index.directories() {
+            std::fs::create_dir_all(base.as_ref().join(dir))?;
         }
         Ok(())
     }
@@ -565,6 +1446,10 @@ impl<R: Read + Seek> ZipReader<R> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::compression_codecs::NoCompressionCodec;
+    use crate::glob::{Glob, PathPrefix};
+    use crate::scanner::{ScanDirection, SignatureScanner};
+    use crate::test_util::{unicode_extra, TempDir, TestArchive, TestEntry};
     use std::io::Cursor;
 
     /// Test that we can find the EOCD signature. when it's aligned, this is the best case scenario.
@@ -588,8 +1473,602 @@ mod tests {
 
         let mut reader = Cursor::new(data);
         let mut buf_reader = BufReader::new(&mut reader);
-        let eocd = find_next_signature(&mut buf_reader, EOCD_SIG, None).unwrap();
+        let eocd = SignatureScanner::new(&mut buf_reader, EOCD_SIG, ScanDirection::Forward)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
         println!("EOCD: {}", eocd);
         assert_eq!(eocd, 0x6A);
     }
+
+    #[test]
+    fn test_find_eocd_ignores_signature_in_data() {
+        let mut fake_eocd = EOCD_SIG.to_le_bytes().to_vec();
+        fake_eocd.extend_from_slice(&[0u8; 18]);
+        let data = TestArchive::new()
+            .add("fake_eocd.bin", &fake_eocd)
+            .add("a.txt", b"hello")
+            .finish();
+        let eocd_offset = data.len() as u64 - EOCD_LEN;
+
+        let mut reader = BufReader::new(Cursor::new(data));
+        let eocd = find_eocd(&mut reader).unwrap();
+        assert_eq!(eocd.offset, eocd_offset);
+        assert_eq!(eocd.total_number_of_central_directory_records, 2);
+    }
+
+    #[test]
+    fn test_find_eocd_rejects_trailing_data() {
+        let mut data = TestArchive::new().add("a.txt", b"hello").finish();
+        data.extend_from_slice(b"trailing garbage");
+        let mut reader = BufReader::new(Cursor::new(data));
+        assert_eq!(
+            find_eocd(&mut reader).unwrap_err(),
+            ZipError::EndOfCentralDirectoryNotFound
+        );
+    }
+
+    #[test]
+    fn test_index_ignores_signature_in_names() {
+        let data = TestArchive::new()
+            .add("PK\x01\x02.txt", b"hello")
+            .add("b.txt", b"world")
+            .finish();
+        let zip = ZipReader::new(Cursor::new(data)).unwrap();
+        assert_eq!(zip.index().len(), 2);
+        assert!(zip.index().contains(Path::new("PK\x01\x02.txt")));
+    }
+
+    #[test]
+    fn test_index_size_mismatch() {
+        let mut data = TestArchive::new().add("a.txt", b"hello").finish();
+        // Grow the size of the central directory recorded in the eocd.
+        let size_pos = data.len() - 10;
+        data[size_pos] += 1;
+        assert_eq!(
+            ZipReader::new(Cursor::new(data)).err().unwrap(),
+            ZipError::CentralDirectorySizeMismatch(52, 51)
+        );
+    }
+
+    #[test]
+    fn test_open_entry() {
+        let data = TestArchive::new()
+            .add("a.txt", b"hello")
+            .add("b.txt", b"world")
+            .finish();
+        let mut zip = ZipReader::new(Cursor::new(data)).unwrap();
+
+        let mut out = Vec::new();
+        let mut entry = zip.open_entry(&"a.txt", &NoCompressionCodec).unwrap();
+        std::io::copy(&mut entry, &mut out).unwrap();
+        drop(entry);
+        assert_eq!(out, b"hello");
+        assert_eq!(
+            zip.extract_file(&"b.txt", &mut NoCompressionCodec).unwrap(),
+            b"world"
+        );
+    }
+
+    /// A codec that only implements the required methods, it can't expand
+    /// entries as they're read.
+    struct UppercaseCodec;
+
+    impl CompressionCodec for UppercaseCodec {
+        fn int_id(&self) -> u16 {
+            0
+        }
+
+        fn compress(&self, data: crate::compression_codecs::MemoryStream) -> Result<Vec<u8>> {
+            Ok(data.0.to_ascii_lowercase())
+        }
+
+        fn expand(&self, data: crate::compression_codecs::MemoryStream) -> Result<Vec<u8>> {
+            Ok(data.0.to_ascii_uppercase())
+        }
+
+        fn streamed_expansion(
+            &self,
+            reader: &mut impl std::io::BufRead,
+            writer: &mut impl std::io::Write,
+        ) -> Result<()> {
+            std::io::copy(reader, writer)?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_default_expanding_reader() {
+        // Failing rather than silently holding the whole entry in memory.
+        let data = TestArchive::new().add("a.txt", b"hello").finish();
+        let mut zip = ZipReader::new(Cursor::new(data)).unwrap();
+        assert!(matches!(
+            zip.open_entry(&"a.txt", &UppercaseCodec),
+            Err(ZipError::InvalidCompressionMethod(0))
+        ));
+        assert!(matches!(
+            zip.extract_file(&"a.txt", &mut UppercaseCodec),
+            Err(ZipError::InvalidCompressionMethod(0))
+        ));
+    }
+
+    #[test]
+    fn test_crc_mismatch() {
+        let mut data = TestArchive::new().add("a.txt", b"hello").finish();
+        // Corrupt the first byte of the file data.
+        data[35] = b'j';
+        let mut zip = ZipReader::new(Cursor::new(data)).unwrap();
+        assert_eq!(
+            zip.extract_file(&"a.txt", &mut NoCompressionCodec)
+                .unwrap_err(),
+            ZipError::CrcMismatch(PathBuf::from("a.txt"), 0x3610A686, 0x4CD0F5E6)
+        );
+
+        zip.set_verify_crc(false);
+        assert_eq!(
+            zip.extract_file(&"a.txt", &mut NoCompressionCodec).unwrap(),
+            b"jello"
+        );
+
+        // Extracting to disk doesn't leave the corrupted file behind.
+        zip.set_verify_crc(true);
+        let dir = TempDir::new("crc");
+        let out = dir.path();
+        assert!(matches!(
+            zip.extract_all_files(&out, &mut NoCompressionCodec),
+            Err(ZipError::CrcMismatch(..))
+        ));
+        assert!(!out.join("a.txt").exists());
+    }
+
+    #[test]
+    fn test_data_descriptor() {
+        let data = TestArchive::new()
+            .add_entry(TestEntry {
+                name: "signed.txt",
+                contents: b"hello",
+                data_descriptor: true,
+                descriptor_signature: true,
+                ..Default::default()
+            })
+            .add_entry(TestEntry {
+                name: "unsigned.txt",
+                contents: b"world",
+                data_descriptor: true,
+                ..Default::default()
+            })
+            .add_entry(TestEntry {
+                name: "zip64.txt",
+                contents: b"zip64 world",
+                zip64: true,
+                data_descriptor: true,
+                descriptor_signature: true,
+                ..Default::default()
+            })
+            .finish();
+        let mut zip = ZipReader::new(Cursor::new(data)).unwrap();
+
+        for (name, contents) in [
+            ("signed.txt", &b"hello"[..]),
+            ("unsigned.txt", b"world"),
+            ("zip64.txt", b"zip64 world"),
+        ] {
+            let cd = zip.index().get(Path::new(name)).unwrap().clone();
+            let header = get_local_file_header(&mut zip.reader, &cd).unwrap();
+            assert_eq!(header.crc32, cd.crc32);
+            assert_eq!(header.compressed_size, contents.len() as u64);
+            assert_eq!(header.uncompressed_size, contents.len() as u64);
+            assert_eq!(
+                zip.extract_file(&name, &mut NoCompressionCodec).unwrap(),
+                contents
+            );
+        }
+    }
+
+    #[test]
+    fn test_prefixed_archive() {
+        let mut archive = TestArchive::new();
+        archive
+            .add("a.txt", b"hello")
+            .add("b.txt", b"world")
+            .prefix(b"#!/bin/sh\nexit 0\n");
+        for data in [archive.finish(), archive.finish_zip64()] {
+            let mut zip = ZipReader::new(Cursor::new(data)).unwrap();
+            assert_eq!(zip.prefix_len(), 17);
+            assert_eq!(zip.prefix().unwrap(), b"#!/bin/sh\nexit 0\n");
+            assert_eq!(zip.file_info(&"a.txt").unwrap().offset, 17);
+            assert_eq!(
+                zip.extract_file(&"b.txt", &mut NoCompressionCodec).unwrap(),
+                b"world"
+            );
+        }
+    }
+
+    #[test]
+    fn test_plain_archive_is_not_zip64() {
+        let data = TestArchive::new().add("a.txt", b"hello").finish();
+        let zip = ZipReader::new(Cursor::new(data)).unwrap();
+        assert!(!zip.is_zip64());
+        assert!(zip.index().contains(Path::new("a.txt")));
+    }
+
+    #[test]
+    fn test_zip64_eocd() {
+        let data = TestArchive::new()
+            .add("a.txt", b"hello")
+            .add("b.txt", b"world")
+            .finish_zip64();
+        let mut zip = ZipReader::new(Cursor::new(data)).unwrap();
+        assert!(zip.is_zip64());
+        assert_eq!(zip.index().len(), 2);
+        assert_eq!(zip.dump_file(&"b.txt").unwrap(), b"world");
+
+        // A record claiming to be larger than the archive is rejected.
+        let mut data = zip.reader.into_inner().into_inner();
+        let size_at = data.len() - 22 - 20 - 56 + 4;
+        data[size_at..size_at + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        let eocd64_at = (size_at - 4) as u64;
+        assert_eq!(
+            ZipReader::new(Cursor::new(data)).err(),
+            Some(ZipError::InvalidEntry(eocd64_at))
+        );
+    }
+
+    #[test]
+    fn test_zip64_extra_field() {
+        let data = TestArchive::new()
+            .add("a.txt", b"hello")
+            .add_entry(TestEntry {
+                name: "b.txt",
+                contents: b"zip64 world",
+                zip64: true,
+                ..Default::default()
+            })
+            .finish();
+        let mut zip = ZipReader::new(Cursor::new(data)).unwrap();
+        let info = zip.file_info(&"b.txt").unwrap();
+        assert_eq!(info.size, 11);
+        assert_eq!(info.compressed_size, 11);
+        assert_eq!(info.offset, 40);
+
+        let cd = zip.index().get(Path::new("b.txt")).unwrap().clone();
+        let header = get_local_file_header(&mut zip.reader, &cd).unwrap();
+        assert_eq!(header.compressed_size, 11);
+        assert_eq!(zip.dump_file(&"b.txt").unwrap(), b"zip64 world");
+
+        // A compressed size far larger than the archive fails the read
+        // instead of the allocation.
+        let mut data = zip.reader.into_inner().into_inner();
+        let extra = &crate::ZIP64_EXTRA_ID.to_le_bytes();
+        let field = memmem::rfind(&data, &[extra[0], extra[1], 24, 0]).unwrap();
+        data[field + 12..field + 20].copy_from_slice(&0xFFFF_FFFF_FFFFu64.to_le_bytes());
+        let mut zip = ZipReader::new(Cursor::new(data)).unwrap();
+        assert_eq!(
+            zip.dump_file(&"b.txt").unwrap_err(),
+            ZipError::IOError(std::io::ErrorKind::UnexpectedEof.into())
+        );
+    }
+
+    #[test]
+    fn test_legacy_names() {
+        let data = TestArchive::new()
+            .add_entry(TestEntry {
+                raw_name: Some(b"\x81ber.txt"),
+                contents: b"hello",
+                ..Default::default()
+            })
+            .add_entry(TestEntry {
+                name: "über-utf8.txt",
+                contents: b"world",
+                flags: crate::encoding::UTF8_FLAG,
+                ..Default::default()
+            })
+            .finish();
+        let mut zip = ZipReader::new(Cursor::new(data)).unwrap();
+        let cd = zip.index().get(Path::new("über.txt")).unwrap();
+        assert_eq!(cd.raw_filename, b"\x81ber.txt");
+        assert!(zip.index().contains(Path::new("über-utf8.txt")));
+
+        zip.set_legacy_decoder(|bytes: &[u8]| bytes.iter().map(|&b| b as char).collect());
+        assert!(zip.index().contains(Path::new("über-utf8.txt")));
+        assert_eq!(
+            zip.extract_file(&"\u{81}ber.txt", &mut NoCompressionCodec)
+                .unwrap(),
+            b"hello"
+        );
+    }
+
+    #[test]
+    fn test_unicode_extra_fields() {
+        let extra = [
+            unicode_extra(UNICODE_PATH_EXTRA_ID, b"??.txt", "日本.txt"),
+            unicode_extra(UNICODE_COMMENT_EXTRA_ID, b"caf\x82", "café"),
+        ]
+        .concat();
+        let data = TestArchive::new()
+            .add_entry(TestEntry {
+                name: "??.txt",
+                contents: b"hello",
+                extra: &extra,
+                comment: b"caf\x82",
+                ..Default::default()
+            })
+            .add("plain.txt", b"world")
+            .finish();
+        let zip = ZipReader::new(Cursor::new(data)).unwrap();
+        let info = zip.file_info(&"日本.txt").unwrap();
+        assert_eq!(info.name_source, NameSource::UnicodeExtra);
+        assert_eq!(info.comment.as_deref(), Some("café"));
+
+        let info = zip.file_info(&"plain.txt").unwrap();
+        assert_eq!(info.name_source, NameSource::Legacy);
+        assert_eq!(info.comment, None);
+    }
+
+    #[test]
+    fn test_entry_times() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        // 2022-10-29 13:45:58
+        let last_mod = ((42 << 9) | (10 << 5) | 29, (13 << 11) | (45 << 5) | 29);
+        let dos_secs = 1667051158;
+        let mut timestamp = Vec::new();
+        ExtraField::ExtendedTimestamp {
+            flags: 7,
+            mtime: Some(dos_secs as i32 + 1),
+            atime: None,
+            ctime: None,
+        }
+        .write(&mut timestamp);
+        let mut local_timestamp = Vec::new();
+        ExtraField::ExtendedTimestamp {
+            flags: 7,
+            mtime: Some(dos_secs as i32 + 1),
+            atime: Some(dos_secs as i32 + 30),
+            ctime: Some(dos_secs as i32 - 30),
+        }
+        .write(&mut local_timestamp);
+        let filetime = |secs: u64| secs * 10_000_000 + 116_444_736_000_000_000;
+        let mut ntfs = timestamp.clone();
+        ExtraField::Ntfs {
+            mtime: filetime(dos_secs + 1) + 5_000_000,
+            atime: 0,
+            ctime: filetime(dos_secs - 60),
+        }
+        .write(&mut ntfs);
+
+        let data = TestArchive::new()
+            .add_entry(TestEntry {
+                name: "dos.txt",
+                last_mod,
+                ..Default::default()
+            })
+            .add_entry(TestEntry {
+                name: "timestamp.txt",
+                last_mod,
+                extra: &timestamp,
+                ..Default::default()
+            })
+            .add_entry(TestEntry {
+                name: "local.txt",
+                last_mod,
+                extra: &timestamp,
+                local_extra: &local_timestamp,
+                ..Default::default()
+            })
+            .add_entry(TestEntry {
+                name: "ntfs.txt",
+                last_mod,
+                extra: &ntfs,
+                ..Default::default()
+            })
+            .finish();
+        let mut zip = ZipReader::new(Cursor::new(data)).unwrap();
+        zip.set_time_zone(TimeZone::Utc);
+        let at = |secs: u64, nanos: u32| Some(UNIX_EPOCH + Duration::new(secs, nanos));
+
+        let info = zip.file_info(&"dos.txt").unwrap();
+        assert_eq!(info.last_modified.to_string(), "2022-10-29 13:45:58");
+        assert_eq!(info.mtime, at(dos_secs, 0));
+        assert_eq!(info.atime, None);
+
+        let info = zip.file_info(&"timestamp.txt").unwrap();
+        assert_eq!(info.mtime, at(dos_secs + 1, 0));
+        assert_eq!(info.atime, None);
+
+        let info = zip.file_info(&"ntfs.txt").unwrap();
+        assert_eq!(info.mtime, at(dos_secs + 1, 500_000_000));
+        assert_eq!(info.atime, None);
+        assert_eq!(info.ctime, at(dos_secs - 60, 0));
+
+        // The access and creation times are only in the local header copy.
+        let info = zip.file_info(&"local.txt").unwrap();
+        assert_eq!(info.atime, None);
+        let info = zip.local_file_info(&"local.txt").unwrap();
+        assert_eq!(info.mtime, at(dos_secs + 1, 0));
+        assert_eq!(info.atime, at(dos_secs + 30, 0));
+        assert_eq!(info.ctime, at(dos_secs - 30, 0));
+        let info = zip.local_file_info(&"ntfs.txt").unwrap();
+        assert_eq!(info.mtime, at(dos_secs + 1, 500_000_000));
+        assert_eq!(info.ctime, at(dos_secs - 60, 0));
+    }
+
+    #[test]
+    fn test_entry_file_types() {
+        let data = TestArchive::new()
+            .add_entry(TestEntry {
+                name: "script.sh",
+                contents: b"#!/bin/sh",
+                unix_mode: Some(0o100755),
+                ..Default::default()
+            })
+            .add_entry(TestEntry {
+                name: "link",
+                contents: b"script.sh",
+                unix_mode: Some(0o120777),
+                ..Default::default()
+            })
+            .add("dos.txt", b"hello")
+            .finish();
+        let zip = ZipReader::new(Cursor::new(data)).unwrap();
+
+        let info = zip.file_info(&"script.sh").unwrap();
+        assert_eq!(info.host_os, HostOs::Unix);
+        assert_eq!(info.file_type, FileType::Regular);
+        assert_eq!(info.unix_mode.map(|mode| mode & 0o7777), Some(0o755));
+        assert!(info.is_file);
+
+        let info = zip.file_info(&"link").unwrap();
+        assert_eq!(info.file_type, FileType::Symlink);
+        assert!(info.is_symlink && !info.is_file);
+
+        let info = zip.file_info(&"dos.txt").unwrap();
+        assert_eq!(info.host_os, HostOs::MsDos);
+        assert_eq!(info.unix_mode, None);
+        assert!(info.is_file);
+    }
+
+    #[test]
+    fn test_directories_and_empty_files() {
+        let data = TestArchive::new()
+            .add("empty.txt", b"")
+            .add("dir/", b"")
+            .add_entry(TestEntry {
+                name: "dos-dir",
+                dos_attributes: 0x10,
+                ..Default::default()
+            })
+            .add("dir/file.txt", b"hello")
+            .finish();
+        let mut zip = ZipReader::new(Cursor::new(data)).unwrap();
+        let files = zip.index().files().map(|cd| cd.filename.as_path());
+        assert_eq!(
+            files.collect::<Vec<_>>(),
+            [Path::new("dir/file.txt"), Path::new("empty.txt")]
+        );
+        assert_eq!(zip.index().dirs().count(), 2);
+        assert!(!zip.file_info(&"empty.txt").unwrap().is_dir);
+        assert!(zip.file_info(&"dos-dir").unwrap().is_dir);
+        // Made on MS-DOS without the directory attribute, only the name says so.
+        let info = zip.file_info(&"dir").unwrap();
+        assert!(info.is_dir);
+        assert!(!info.is_file);
+        assert_eq!(info.file_type, FileType::Directory);
+
+        let dir = TempDir::new("dirs");
+        let out = dir.path().join("out");
+        zip.extract_all_files(&out, &mut NoCompressionCodec)
+            .unwrap();
+        assert!(out.join("empty.txt").is_file());
+        assert_eq!(std::fs::read(out.join("empty.txt")).unwrap(), b"");
+        assert!(out.join("dos-dir").is_dir());
+        assert_eq!(std::fs::read(out.join("dir/file.txt")).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_comments() {
+        let data = TestArchive::new()
+            .comment(b"built by ci #42 \x81")
+            .add_entry(TestEntry {
+                name: "a.txt",
+                comment: b"commit 1a2b3c",
+                ..Default::default()
+            })
+            .add("b.txt", b"")
+            .finish();
+        let zip = ZipReader::new(Cursor::new(data)).unwrap();
+        assert_eq!(zip.archive_comment_raw(), b"built by ci #42 \x81");
+        assert_eq!(zip.archive_comment().as_deref(), Some("built by ci #42 ü"));
+        assert_eq!(
+            zip.entry_comment(&"a.txt").unwrap().as_deref(),
+            Some("commit 1a2b3c")
+        );
+        assert_eq!(zip.entry_comment(&"b.txt").unwrap(), None);
+
+        let data = TestArchive::new()
+            .comment("überall".as_bytes())
+            .add_entry(TestEntry {
+                name: "a.txt",
+                flags: UTF8_FLAG,
+                ..Default::default()
+            })
+            .finish();
+        let zip = ZipReader::new(Cursor::new(data)).unwrap();
+        assert_eq!(zip.archive_comment().as_deref(), Some("überall"));
+        assert_eq!(
+            zip.end_of_central_directory()
+                .total_number_of_central_directory_records,
+            1
+        );
+    }
+
+    #[test]
+    fn test_extract_implicit_directories() {
+        let data = TestArchive::new()
+            .add("a/b/c.txt", b"hello")
+            .add("d/e.txt", b"world")
+            .finish();
+        let mut zip = ZipReader::new(Cursor::new(data)).unwrap();
+        let dir = TempDir::new("implicit");
+        let out = dir.path();
+        zip.extract_all_files(&out, &mut NoCompressionCodec)
+            .unwrap();
+        assert_eq!(std::fs::read(out.join("a/b/c.txt")).unwrap(), b"hello");
+        assert_eq!(std::fs::read(out.join("d/e.txt")).unwrap(), b"world");
+    }
+
+    #[test]
+    fn test_extract_matching() {
+        let data = TestArchive::new()
+            .add("assets/a.png", b"a")
+            .add("assets/ui/b.png", b"b")
+            .add("assets/ui/c.txt", b"c")
+            .add("docs/d.png", b"d")
+            .finish();
+        let mut zip = ZipReader::new(Cursor::new(data)).unwrap();
+        let dir = TempDir::new("matching");
+        let out = dir.path();
+        let glob = Glob::new("assets/**/*.png").unwrap();
+        zip.extract_matching(&out, &mut NoCompressionCodec, &glob)
+            .unwrap();
+        assert_eq!(std::fs::read(out.join("assets/ui/b.png")).unwrap(), b"b");
+        assert!(out.join("assets/a.png").exists());
+        assert!(!out.join("assets/ui/c.txt").exists());
+        assert!(!out.join("docs").exists());
+
+        zip.extract_matching(&out, &mut NoCompressionCodec, &PathPrefix::new("docs"))
+            .unwrap();
+        assert!(out.join("docs/d.png").exists());
+    }
+
+    #[test]
+    fn test_extract_unsafe_paths() {
+        let dir = TempDir::new("unsafe");
+        let root = dir.path();
+        let out = root.join("out");
+        std::fs::create_dir_all(&out).unwrap();
+        for name in ["../slipped/evil.txt", "/abs/evil.txt"] {
+            let data = TestArchive::new()
+                .add("safe.txt", b"safe")
+                .add(name, b"evil")
+                .finish();
+            let mut zip = ZipReader::new(Cursor::new(data)).unwrap();
+            let unsafe_path = ZipError::UnsafePath(PathBuf::from(name));
+            assert_eq!(
+                zip.extract_all_files(&out, &mut NoCompressionCodec)
+                    .unwrap_err(),
+                unsafe_path
+            );
+            let all = Glob::new("**").unwrap();
+            assert_eq!(
+                zip.extract_matching(&out, &mut NoCompressionCodec, &all)
+                    .unwrap_err(),
+                unsafe_path
+            );
+        }
+        // Nothing was written, not even the safe entry.
+        assert!(!out.join("safe.txt").exists());
+        assert!(!root.join("slipped").exists());
+    }
 }
@@ -0,0 +1,200 @@
+/*
+   Zip file reader and writer, in pure Rust.
+   Copyright (C) 2022 Matheus Xavier <mxavier@neonimp.com>
+
+   This program is free software: you can redistribute it and/or modify
+   it under the terms of the GNU Lesser General Public License as published by
+   the Free Software Foundation, either version 3 of the License, or
+   (at your option) any later version.
+
+   This program is distributed in the hope that it will be useful,
+   but WITHOUT ANY WARRANTY; without even the implied warranty of
+   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+   GNU General Public License for more details.
+
+   You should have received a copy of the GNU Lesser General Public License
+   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Buffered scanning for record signatures, used by the recovery paths that
+//! can't rely on the central directory.
+
+use memchr::memmem::Finder;
+use std::collections::VecDeque;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+
+use crate::Result;
+
+/// How much of the stream is searched at once.
+const CHUNK_LEN: u64 = 64 * 1024;
+
+/// Signatures are 4 bytes long, consecutive chunks overlap by this much so a
+/// signature split across two of them is still found.
+const OVERLAP: u64 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanDirection {
+    /// From the start of the range towards the end, matches come out in
+    /// ascending order.
+    Forward,
+    /// From the end of the range towards the start, matches come out in
+    /// descending order.
+    Reverse,
+}
+
+/// Iterator over the offsets of every occurrence of a signature in a stream.
+///
+/// The stream is read in large chunks that are searched with a vectorized
+/// substring search, so scanning costs about as much as reading the range once.
+/// The position of the reader is left wherever the last read put it.
+pub struct SignatureScanner<'r, R: Read + Seek> {
+    reader: &'r mut R,
+    finder: Finder<'static>,
+    direction: ScanDirection,
+    /// The part of the range that hasn't been searched yet.
+    remaining: Range<u64>,
+    buf: Vec<u8>,
+    matches: VecDeque<u64>,
+    done: bool,
+}
+
+impl<'r, R: Read + Seek> SignatureScanner<'r, R> {
+    /// Scan the whole stream for `signature`.
+    pub fn new(reader: &'r mut R, signature: u32, direction: ScanDirection) -> Result<Self> {
+        let len = reader.seek(SeekFrom::End(0))?;
+        Ok(Self::with_range(reader, signature, direction, 0..len))
+    }
+
+    /// Scan only `range` of the stream for `signature`, a match has to fit
+    /// entirely inside the range.
+    pub fn with_range(
+        reader: &'r mut R,
+        signature: u32,
+        direction: ScanDirection,
+        range: Range<u64>,
+    ) -> Self {
+        SignatureScanner {
+            reader,
+            finder: Finder::new(&signature.to_le_bytes()).into_owned(),
+            direction,
+            remaining: range,
+            buf: Vec::new(),
+            matches: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    /// Read and search the next chunk, queueing up its matches.
+    fn scan_chunk(&mut self) -> Result<()> {
+        let Range { start, end } = self.remaining;
+        let chunk = match self.direction {
+            ScanDirection::Forward => start..end.min(start + CHUNK_LEN),
+            ScanDirection::Reverse => end.saturating_sub(CHUNK_LEN).max(start)..end,
+        };
+
+        self.buf.resize((chunk.end - chunk.start) as usize, 0);
+        self.reader.seek(SeekFrom::Start(chunk.start))?;
+        self.reader.read_exact(&mut self.buf)?;
+
+        match self.direction {
+            ScanDirection::Forward => {
+                let found = self.finder.find_iter(&self.buf);
+                self.matches
+                    .extend(found.map(|pos| chunk.start + pos as u64));
+                if chunk.end == end {
+                    self.done = true;
+                } else {
+                    self.remaining.start = chunk.end - OVERLAP;
+                }
+            }
+            ScanDirection::Reverse => {
+                let needle = self.finder.needle();
+                let found = memchr::memmem::rfind_iter(&self.buf, needle);
+                self.matches
+                    .extend(found.map(|pos| chunk.start + pos as u64));
+                if chunk.start == start {
+                    self.done = true;
+                } else {
+                    self.remaining.end = chunk.start + OVERLAP;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'r, R: Read + Seek> Iterator for SignatureScanner<'r, R> {
+    type Item = Result<u64>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(offset) = self.matches.pop_front() {
+                return Some(Ok(offset));
+            }
+            if self.done || self.remaining.end - self.remaining.start < 4 {
+                return None;
+            }
+            if let Err(e) = self.scan_chunk() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CD_SIG;
+    use std::io::Cursor;
+
+    /// A stream a few chunks long with signatures right on the chunk edges.
+    fn sample() -> (Vec<u8>, Vec<u64>) {
+        let mut data = vec![0u8; (CHUNK_LEN * 3) as usize];
+        let offsets = vec![0, 17, CHUNK_LEN - 2, CHUNK_LEN * 2 - 4, CHUNK_LEN * 3 - 4];
+        for &offset in &offsets {
+            let offset = offset as usize;
+            data[offset..offset + 4].copy_from_slice(&CD_SIG.to_le_bytes());
+        }
+        (data, offsets)
+    }
+
+    #[test]
+    fn test_scan_forward() {
+        let (data, offsets) = sample();
+        let mut cursor = Cursor::new(data);
+        let found = SignatureScanner::new(&mut cursor, CD_SIG, ScanDirection::Forward)
+            .unwrap()
+            .collect::<Result<Vec<u64>>>()
+            .unwrap();
+        assert_eq!(found, offsets);
+    }
+
+    #[test]
+    fn test_scan_reverse() {
+        let (data, mut offsets) = sample();
+        let mut cursor = Cursor::new(data);
+        let found = SignatureScanner::new(&mut cursor, CD_SIG, ScanDirection::Reverse)
+            .unwrap()
+            .collect::<Result<Vec<u64>>>()
+            .unwrap();
+        offsets.reverse();
+        assert_eq!(found, offsets);
+    }
+
+    #[test]
+    fn test_scan_range() {
+        let (data, _) = sample();
+        let mut cursor = Cursor::new(data);
+        let found = SignatureScanner::with_range(
+            &mut cursor,
+            CD_SIG,
+            ScanDirection::Forward,
+            1..CHUNK_LEN * 2,
+        )
+        .collect::<Result<Vec<u64>>>()
+        .unwrap();
+        assert_eq!(found, vec![17, CHUNK_LEN - 2, CHUNK_LEN * 2 - 4]);
+    }
+}
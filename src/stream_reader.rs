@@ -0,0 +1,457 @@
+/*
+   Zip file reader and writer, in pure Rust.
+   Copyright (C) 2022 Matheus Xavier <mxavier@neonimp.com>
+
+   This program is free software: you can redistribute it and/or modify
+   it under the terms of the GNU Lesser General Public License as published by
+   the Free Software Foundation, either version 3 of the License, or
+   (at your option) any later version.
+
+   This program is distributed in the hope that it will be useful,
+   but WITHOUT ANY WARRANTY; without even the implied warranty of
+   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+   GNU General Public License for more details.
+
+   You should have received a copy of the GNU Lesser General Public License
+   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Forward only reading of archives from streams that can't seek, like pipes,
+//! sockets or HTTP bodies, by walking the local file headers in order.
+
+use memchr::memmem;
+use neoncore::int_util::Endianness::LittleEndian;
+use neoncore::int_util::StreamReadInt;
+use std::cell::Cell;
+use std::io::{self, BufRead, Read};
+use std::rc::Rc;
+
+use crate::compression_codecs::CompressionCodec;
+use crate::encoding::{Cp437, LegacyDecoder};
+use crate::extra_field::ExtraField;
+use crate::reader::{parse_data_descriptor, read_local_header, ZipEntryReader};
+use crate::structures::{DataDescriptor, LocalFileHeader};
+use crate::{Result, ZipError, CD_SIG, DD_SIG, EOCD64_SIG, EOCD_SIG, LFH_SIG};
+
+/// How much is read from the stream at once.
+const CHUNK_LEN: usize = 64 * 1024;
+
+/// Longest data descriptor, signature and Zip64 sizes included, plus the
+/// signature of the record that follows it. Data closer than this to the end of
+/// the buffer could still be the start of a descriptor.
+const DESCRIPTOR_TAIL: usize = 24 + 4;
+
+/// Buffer over the input that can look further ahead than a [`io::BufReader`],
+/// which is needed to recognize a data descriptor before handing out the bytes
+/// in front of it.
+struct Lookahead<R: Read> {
+    inner: R,
+    buf: Vec<u8>,
+    pos: usize,
+    eof: bool,
+    /// How many bytes have been consumed from the stream.
+    consumed: u64,
+}
+
+impl<R: Read> Lookahead<R> {
+    /// Make at least `min` bytes available, unless the input runs out first.
+    fn fill(&mut self, min: usize) -> io::Result<&[u8]> {
+        if self.buf.len() - self.pos < min && !self.eof {
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+            while self.buf.len() < min {
+                let filled = self.buf.len();
+                self.buf.resize(filled + CHUNK_LEN.max(min - filled), 0);
+                let read = self.inner.read(&mut self.buf[filled..]);
+                self.buf
+                    .truncate(filled + read.as_ref().map_or(0, |read| *read));
+                if read? == 0 {
+                    self.eof = true;
+                    break;
+                }
+            }
+        }
+        Ok(&self.buf[self.pos..])
+    }
+}
+
+impl<R: Read> Read for Lookahead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: Read> BufRead for Lookahead<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.fill(1)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+        self.consumed += amt as u64;
+    }
+}
+
+/// Where the file data of the current entry ends.
+enum EntryData {
+    /// There is no current entry, or all of its data has been read.
+    Done,
+    /// The compressed size is known from the local file header, the data may
+    /// still be followed by a data descriptor.
+    Sized {
+        remaining: u64,
+        descriptor: bool,
+        zip64: bool,
+    },
+    /// The compressed size is only in the data descriptor after the data, so
+    /// the end has to be found by looking for it.
+    Unsized { read: u64, zip64: bool },
+}
+
+/// Reads the entries of an archive front to back from a stream that can't
+/// seek, the central directory is never consulted.
+///
+/// Entries written with a data descriptor and no sizes in the local header are
+/// supported by looking for the descriptor right before the next record, the
+/// crc32 and sizes of the header are filled in once it's found.
+pub struct ZipStreamReader<R: Read> {
+    input: Lookahead<R>,
+    header: Option<LocalFileHeader>,
+    data: EntryData,
+    expected_crc: Rc<Cell<Option<u32>>>,
+    verify_crc: bool,
+    legacy_decoder: Box<dyn LegacyDecoder>,
+    finished: bool,
+}
+
+impl<R: Read> ZipStreamReader<R> {
+    pub fn new(reader: R) -> Self {
+        ZipStreamReader {
+            input: Lookahead {
+                inner: reader,
+                buf: Vec::new(),
+                pos: 0,
+                eof: false,
+                consumed: 0,
+            },
+            header: None,
+            data: EntryData::Done,
+            expected_crc: Rc::new(Cell::new(None)),
+            verify_crc: true,
+            legacy_decoder: Box::new(Cp437),
+            finished: false,
+        }
+    }
+
+    /// Choose whether the data read through [`ZipStreamReader::entry_reader`]
+    /// is checked against the CRC-32 in the archive, this is on by default.
+    pub fn set_verify_crc(&mut self, verify: bool) {
+        self.verify_crc = verify;
+    }
+
+    /// Decode the names of the following entries that are not flagged as UTF-8
+    /// with `decoder` instead of code page 437.
+    pub fn set_legacy_decoder(&mut self, decoder: impl LegacyDecoder + 'static) {
+        self.legacy_decoder = Box::new(decoder);
+    }
+
+    /// Skip whatever is left of the current entry and read the next local file
+    /// header, returns `None` once the central directory is reached.
+    pub fn next_entry(&mut self) -> Result<Option<LocalFileHeader>> {
+        if self.finished {
+            return Ok(None);
+        }
+        io::copy(&mut RawEntryData(self), &mut io::sink())?;
+
+        let offset = self.input.consumed;
+        let signature = self.input.read_u32(LittleEndian)?;
+        match signature {
+            LFH_SIG => {}
+            CD_SIG | EOCD64_SIG | EOCD_SIG => {
+                self.finished = true;
+                self.header = None;
+                return Ok(None);
+            }
+            _ => return Err(ZipError::InvalidSignature(signature)),
+        }
+
+        let header = read_local_header(&mut self.input, offset, &*self.legacy_decoder)?;
+        let zip64 = header
+            .extra_fields()
+            .any(|field| matches!(field, ExtraField::Zip64 { .. }));
+        let descriptor = header.flags & 1 << 3 != 0;
+        self.data = if descriptor && header.compressed_size == 0 {
+            EntryData::Unsized { read: 0, zip64 }
+        } else {
+            EntryData::Sized {
+                remaining: header.compressed_size,
+                descriptor,
+                zip64,
+            }
+        };
+        self.expected_crc.set((!descriptor).then_some(header.crc32));
+        self.header = Some(header.clone());
+        Ok(Some(header))
+    }
+
+    /// The header of the current entry, the crc32 and sizes from the data
+    /// descriptor are filled in once all of its data has been read.
+    pub fn header(&self) -> Option<&LocalFileHeader> {
+        self.header.as_ref()
+    }
+
+    /// Open a reader that expands the data of the current entry with `codec`.
+    pub fn entry_reader(&mut self, codec: &impl CompressionCodec) -> Result<ZipEntryReader<'_>> {
+        let header = self
+            .header
+            .as_ref()
+            .ok_or(ZipError::InvalidEntry(self.input.consumed))?;
+        if header.compression != codec.int_id() {
+            return Err(ZipError::MismatchedCompressionMethod(
+                header.compression,
+                codec.int_id(),
+            ));
+        }
+        let name = header.filename.clone();
+        let expected_crc = self.expected_crc.clone();
+        let verify_crc = self.verify_crc;
+        let raw = io::BufReader::new(RawEntryData(self));
+        Ok(
+            ZipEntryReader::new(codec.expanding_reader(Box::new(raw))?, name, expected_crc)
+                .verify_crc(verify_crc),
+        )
+    }
+
+    /// Read the data descriptor at the current position and fill in the header.
+    fn finish_descriptor(&mut self, zip64: bool) -> io::Result<DataDescriptor> {
+        let offset = self.input.consumed;
+        let descriptor = parse_data_descriptor(&mut self.input, offset, zip64)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if let Some(header) = &mut self.header {
+            header.crc32 = descriptor.crc32;
+            header.compressed_size = descriptor.compressed_size;
+            header.uncompressed_size = descriptor.uncompressed_size;
+        }
+        self.expected_crc.set(Some(descriptor.crc32));
+        Ok(descriptor)
+    }
+}
+
+/// Look for the data descriptor of an entry in `data`, `read` bytes into the
+/// entry. Returns its position, which is where the entry data ends.
+///
+/// A descriptor is recognized by the local file header or central directory
+/// signature that follows it, and by its compressed size matching the amount
+/// of data in front of it. Both the signed and unsigned forms are checked.
+fn find_descriptor(data: &[u8], read: u64, zip64: bool) -> Option<usize> {
+    let fields_len = if zip64 { 20 } else { 12 };
+    let size_at = |pos: usize| -> Option<u64> {
+        Some(if zip64 {
+            u64::from_le_bytes(data.get(pos..pos + 8)?.try_into().unwrap())
+        } else {
+            u32::from_le_bytes(data.get(pos..pos + 4)?.try_into().unwrap()) as u64
+        })
+    };
+
+    memmem::find_iter(data, b"PK")
+        .filter(|&next| {
+            matches!(
+                data.get(next..next + 4),
+                Some(sig) if sig == LFH_SIG.to_le_bytes() || sig == CD_SIG.to_le_bytes()
+            )
+        })
+        .find_map(|next| {
+            let unsigned = next.checked_sub(fields_len);
+            let signed = next
+                .checked_sub(fields_len + 4)
+                .filter(|&pos| data[pos..pos + 4] == DD_SIG.to_le_bytes());
+            [
+                signed.map(|pos| (pos, pos + 8)),
+                unsigned.map(|pos| (pos, pos + 4)),
+            ]
+            .into_iter()
+            .flatten()
+            .find(|&(pos, size_pos)| size_at(size_pos) == Some(read + pos as u64))
+            .map(|(pos, _)| pos)
+        })
+}
+
+/// The raw, still compressed, data of the current entry.
+struct RawEntryData<'a, R: Read>(&'a mut ZipStreamReader<R>);
+
+impl<R: Read> Read for RawEntryData<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let reader = &mut *self.0;
+        match reader.data {
+            EntryData::Done => Ok(0),
+            EntryData::Sized {
+                remaining,
+                descriptor,
+                zip64,
+            } => {
+                if remaining == 0 {
+                    if descriptor {
+                        reader.finish_descriptor(zip64)?;
+                    }
+                    reader.data = EntryData::Done;
+                    return Ok(0);
+                }
+                let len = buf.len().min(remaining.min(usize::MAX as u64) as usize);
+                let read = reader.input.read(&mut buf[..len])?;
+                if read == 0 && len > 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                reader.data = EntryData::Sized {
+                    remaining: remaining - read as u64,
+                    descriptor,
+                    zip64,
+                };
+                Ok(read)
+            }
+            EntryData::Unsized { read, zip64 } => {
+                reader.input.fill(CHUNK_LEN)?;
+                let eof = reader.input.eof;
+                let available = &reader.input.buf[reader.input.pos..];
+                let len = match find_descriptor(available, read, zip64) {
+                    Some(0) => {
+                        reader.finish_descriptor(zip64)?;
+                        reader.data = EntryData::Done;
+                        return Ok(0);
+                    }
+                    Some(pos) => pos,
+                    None if available.is_empty() => {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    None if eof => available.len(),
+                    None => available.len().saturating_sub(DESCRIPTOR_TAIL),
+                };
+                let len = len.min(buf.len());
+                buf[..len].copy_from_slice(&available[..len]);
+                reader.input.consume(len);
+                reader.data = EntryData::Unsized {
+                    read: read + len as u64,
+                    zip64,
+                };
+                Ok(len)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression_codecs::NoCompressionCodec;
+    use crate::test_util::{TestArchive, TestEntry};
+    use std::path::Path;
+
+    #[test]
+    fn test_stream_entries() {
+        let data = TestArchive::new()
+            .add("a.txt", b"hello")
+            .add_entry(TestEntry {
+                name: "signed.txt",
+                contents: b"PK\x03\x04 isn't a header",
+                data_descriptor: true,
+                descriptor_signature: true,
+                ..Default::default()
+            })
+            .add("skipped.txt", b"never read")
+            .add_entry(TestEntry {
+                name: "unsigned.txt",
+                contents: b"world",
+                data_descriptor: true,
+                ..Default::default()
+            })
+            .add_entry(TestEntry {
+                name: "zip64.txt",
+                contents: b"zip64 world",
+                zip64: true,
+                data_descriptor: true,
+                descriptor_signature: true,
+                ..Default::default()
+            })
+            .finish();
+        let mut zip = ZipStreamReader::new(data.as_slice());
+
+        let read_entry = |zip: &mut ZipStreamReader<&[u8]>, name: &str| {
+            let header = zip.next_entry().unwrap().unwrap();
+            assert_eq!(header.filename, Path::new(name));
+            let mut out = Vec::new();
+            zip.entry_reader(&NoCompressionCodec)
+                .unwrap()
+                .read_to_end(&mut out)
+                .unwrap();
+            assert_eq!(zip.header().unwrap().compressed_size, out.len() as u64);
+            out
+        };
+
+        assert_eq!(read_entry(&mut zip, "a.txt"), b"hello");
+        assert_eq!(
+            read_entry(&mut zip, "signed.txt"),
+            b"PK\x03\x04 isn't a header"
+        );
+        let skipped = zip.next_entry().unwrap().unwrap();
+        assert_eq!(skipped.filename, Path::new("skipped.txt"));
+        assert_eq!(read_entry(&mut zip, "unsigned.txt"), b"world");
+        assert_eq!(read_entry(&mut zip, "zip64.txt"), b"zip64 world");
+        assert!(zip.next_entry().unwrap().is_none());
+        assert!(zip.next_entry().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_stream_skips_descriptor_entries() {
+        let data = TestArchive::new()
+            .add_entry(TestEntry {
+                name: "a.txt",
+                contents: b"hello",
+                data_descriptor: true,
+                ..Default::default()
+            })
+            .add("b.txt", b"world")
+            .finish();
+        let mut zip = ZipStreamReader::new(data.as_slice());
+        zip.next_entry().unwrap().unwrap();
+        let header = zip.next_entry().unwrap().unwrap();
+        assert_eq!(header.filename, Path::new("b.txt"));
+        assert!(zip.next_entry().unwrap().is_none());
+    }
+
+    /// Hands out the data a few bytes at a time, like a slow socket.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(3);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    #[test]
+    fn test_stream_short_descriptor_entry() {
+        let data = TestArchive::new()
+            .add_entry(TestEntry {
+                name: "short.txt",
+                contents: b"hi",
+                data_descriptor: true,
+                ..Default::default()
+            })
+            .add("b.txt", b"world")
+            .finish();
+        let mut zip = ZipStreamReader::new(Trickle(&data));
+        zip.next_entry().unwrap().unwrap();
+        let mut out = Vec::new();
+        zip.entry_reader(&NoCompressionCodec)
+            .unwrap()
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, b"hi");
+        assert_eq!(zip.header().unwrap().compressed_size, 2);
+        let header = zip.next_entry().unwrap().unwrap();
+        assert_eq!(header.filename, Path::new("b.txt"));
+    }
+}
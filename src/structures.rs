@@ -18,6 +18,10 @@
 
 use std::path::PathBuf;
 
+use crate::attributes::{self, FileType, HostOs};
+use crate::encoding::NameSource;
+use crate::extra_field::ExtraFields;
+
 /// Describes a file in the zip archive.
 #[derive(Debug, Clone)]
 pub struct LocalFileHeader {
@@ -35,25 +39,47 @@ pub struct LocalFileHeader {
     pub last_mod_date: u16,
     /// The crc32 checksum of the file.
     pub crc32: u32,
-    /// The size of the file after compression.
-    pub compressed_size: u32,
-    /// The size of the file before compression.
-    pub uncompressed_size: u32,
-    /// The filename of the file.
+    /// The size of the file after compression, taken from the Zip64
+    /// extended information extra field when the header value is saturated.
+    pub compressed_size: u64,
+    /// The size of the file before compression, taken from the Zip64
+    /// extended information extra field when the header value is saturated.
+    pub uncompressed_size: u64,
+    /// The filename of the file, decoded as UTF-8 or with the legacy decoder
+    /// depending on bit 11 of the flags.
     pub filename: PathBuf,
+    /// The filename as it's stored in the archive.
+    pub raw_filename: Vec<u8>,
+    /// Where `filename` was decoded from.
+    pub name_source: NameSource,
     /// The extra field of the file.
     pub extra_field: Vec<u8>,
     /// The offset of the file data in the file.
     pub data_offset: u64,
 }
 
+impl LocalFileHeader {
+    /// Parse the records of the extra field.
+    pub fn extra_fields(&self) -> ExtraFields<'_> {
+        ExtraFields::new(&self.extra_field)
+    }
+}
+
 /// This comes after the file data if the bit 3 in the flags field is set.
 /// this means the values for the crc32, compressed_size, and uncompressed_size
 /// are stored here instead of in the LocalFileHeader.
+///
+/// The signature is optional, and the sizes are 8 bytes long instead of 4 when
+/// the local file header has a Zip64 extended information extra field.
+#[derive(Debug, Clone)]
 pub struct DataDescriptor {
+    /// The offset of the descriptor in the file.
+    pub offset: u64,
     pub crc32: u32,
-    pub compressed_size: u32,
-    pub uncompressed_size: u32,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+    /// The length of the descriptor, including the signature if present.
+    pub len: u64,
 }
 
 /// Due to the way the zip format is designed, the central directory is
@@ -68,23 +94,64 @@ pub struct CentralDirectory {
     pub last_mod_time: u16,
     pub last_mod_date: u16,
     pub crc32: u32,
-    pub compressed_size: u32,
-    pub uncompressed_size: u32,
+    /// Resolved through the Zip64 extended information extra field if needed.
+    pub compressed_size: u64,
+    /// Resolved through the Zip64 extended information extra field if needed.
+    pub uncompressed_size: u64,
+    /// Decoded as UTF-8 or with the legacy decoder depending on bit 11 of the
+    /// flags.
     pub filename: PathBuf,
+    /// The filename as it's stored in the archive.
+    pub raw_filename: Vec<u8>,
+    /// Where `filename` was decoded from.
+    pub name_source: NameSource,
     pub extra_field: Vec<u8>,
     pub file_comment: Vec<u8>,
-    pub disk_number_start: u16,
+    /// Resolved through the Zip64 extended information extra field if needed.
+    pub disk_number_start: u32,
     pub internal_file_attributes: u16,
     pub external_file_attributes: u32,
-    pub local_header_rel_offset: u32,
+    /// Resolved through the Zip64 extended information extra field if needed.
+    pub local_header_rel_offset: u64,
+    /// Named with a trailing slash or marked as a directory in the attributes.
     pub is_directory: bool,
     pub len: u64,
 }
 
+impl CentralDirectory {
+    /// Parse the records of the extra field.
+    pub fn extra_fields(&self) -> ExtraFields<'_> {
+        ExtraFields::new(&self.extra_field)
+    }
+
+    /// The system that made the entry, which decides what the external
+    /// attributes mean.
+    pub fn host_os(&self) -> HostOs {
+        HostOs::from_version_made_by(self.version_made_by)
+    }
+
+    /// The Unix `st_mode` of the entry, with the file type and permission
+    /// bits, if it was made on a Unix-like system.
+    pub fn unix_mode(&self) -> Option<u32> {
+        attributes::unix_mode(self.version_made_by, self.external_file_attributes)
+    }
+
+    /// The type of the entry according to its external attributes, always a
+    /// directory for entries named with a trailing slash.
+    pub fn file_type(&self) -> FileType {
+        if self.is_directory {
+            return FileType::Directory;
+        }
+        attributes::file_type(self.version_made_by, self.external_file_attributes)
+    }
+}
+
 /// Very last structure in a zip archive, it has information that
 /// helps the reader find the central directory.
 #[derive(Debug, Clone)]
 pub struct EndOfCentralDirectory {
+    /// The offset of the record in the file.
+    pub offset: u64,
     pub disk_number: u16,
     pub disk_with_central_directory: u16,
     pub number_of_central_directory_records_on_this_disk: u16,
@@ -94,8 +161,25 @@ pub struct EndOfCentralDirectory {
     pub zip_file_comment: Vec<u8>,
 }
 
+/// Sits right before the [`EndOfCentralDirectory`] of a Zip64 archive and
+/// points at the [`EndOfCentralDirectory64`] record.
+#[derive(Debug, Clone)]
+pub struct EndOfCentralDirectory64Locator {
+    /// The offset of the locator in the file.
+    pub offset: u64,
+    pub disk_with_end_of_central_directory64: u32,
+    pub offset_of_end_of_central_directory64: u64,
+    pub total_number_of_disks: u32,
+}
+
+/// Zip64 version of the [`EndOfCentralDirectory`], used when the archive has
+/// more entries or bytes than the 16 and 32 bit fields can describe.
+#[derive(Debug, Clone)]
 pub struct EndOfCentralDirectory64 {
-    pub size_of_end_of_central_directory: u32,
+    /// The offset of the record in the file.
+    pub offset: u64,
+    /// Size of the remaining record, not counting the signature and this field.
+    pub size_of_end_of_central_directory: u64,
     pub version_made_by: u16,
     pub version_needed_to_extract: u16,
     pub disk_number: u32,
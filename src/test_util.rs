@@ -0,0 +1,296 @@
+/*
+   Zip file reader and writer, in pure Rust.
+   Copyright (C) 2022 Matheus Xavier <mxavier@neonimp.com>
+
+   This program is free software: you can redistribute it and/or modify
+   it under the terms of the GNU Lesser General Public License as published by
+   the Free Software Foundation, either version 3 of the License, or
+   (at your option) any later version.
+
+   This program is distributed in the hope that it will be useful,
+   but WITHOUT ANY WARRANTY; without even the implied warranty of
+   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+   GNU General Public License for more details.
+
+   You should have received a copy of the GNU Lesser General Public License
+   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Helpers to build small archives in memory for the unit tests.
+
+use std::path::{Path, PathBuf};
+
+use crate::{
+    CD_SIG, DD_SIG, EOCD64_LOCATOR_SIG, EOCD64_SIG, EOCD_SIG, LFH_SIG, ZIP64_EXTRA_ID, ZIP_CRC,
+};
+
+/// A single stored entry to be written by [`TestArchive`].
+#[derive(Default)]
+pub(crate) struct TestEntry<'a> {
+    pub name: &'a str,
+    /// Store these bytes as the name instead of `name`.
+    pub raw_name: Option<&'a [u8]>,
+    /// Extra bits for the general purpose flags.
+    pub flags: u16,
+    /// Extra fields for both the local and central headers.
+    pub extra: &'a [u8],
+    /// Extra fields for the local header only, after `extra`.
+    pub local_extra: &'a [u8],
+    pub comment: &'a [u8],
+    /// DOS modification date and time.
+    pub last_mod: (u16, u16),
+    /// Made on Unix, with this `st_mode` in the external attributes, instead
+    /// of MS-DOS.
+    pub unix_mode: Option<u32>,
+    /// MS-DOS attributes.
+    pub dos_attributes: u8,
+    pub contents: &'a [u8],
+    /// Saturate the sizes and offset and store them in a Zip64 extra field.
+    pub zip64: bool,
+    /// Set bit 3 of the flags and write the crc and sizes in a data descriptor.
+    pub data_descriptor: bool,
+    /// Start the data descriptor with its optional signature.
+    pub descriptor_signature: bool,
+}
+
+/// Builds a stored (uncompressed) archive one entry at a time.
+#[derive(Default)]
+pub(crate) struct TestArchive {
+    prefix: Vec<u8>,
+    comment: Vec<u8>,
+    data: Vec<u8>,
+    central: Vec<u8>,
+    entries: u64,
+}
+
+impl TestArchive {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Put `prefix` in front of the archive without adjusting the offsets,
+    /// like a self-extracting archive does.
+    pub fn prefix(&mut self, prefix: &[u8]) -> &mut Self {
+        self.prefix = prefix.to_vec();
+        self
+    }
+
+    /// Set the archive comment, stored in the end of central directory.
+    pub fn comment(&mut self, comment: &[u8]) -> &mut Self {
+        self.comment = comment.to_vec();
+        self
+    }
+
+    pub fn add(&mut self, name: &str, contents: &[u8]) -> &mut Self {
+        self.add_entry(TestEntry {
+            name,
+            contents,
+            ..Default::default()
+        })
+    }
+
+    pub fn add_entry(&mut self, entry: TestEntry) -> &mut Self {
+        let crc = ZIP_CRC.checksum(entry.contents);
+        let mut offset = self.data.len() as u32;
+        let mut size = entry.contents.len() as u32;
+        let name = entry.raw_name.unwrap_or(entry.name.as_bytes());
+        let flags = entry.flags | if entry.data_descriptor { 1 << 3 } else { 0 };
+        let mut local_extra = Vec::new();
+        let mut central_extra = Vec::new();
+        if entry.zip64 {
+            let size64 = (size as u64).to_le_bytes();
+            local_extra.extend_from_slice(&ZIP64_EXTRA_ID.to_le_bytes());
+            local_extra.extend_from_slice(&16u16.to_le_bytes());
+            local_extra.extend_from_slice(&size64);
+            local_extra.extend_from_slice(&size64);
+            central_extra.extend_from_slice(&ZIP64_EXTRA_ID.to_le_bytes());
+            central_extra.extend_from_slice(&24u16.to_le_bytes());
+            central_extra.extend_from_slice(&size64);
+            central_extra.extend_from_slice(&size64);
+            central_extra.extend_from_slice(&(offset as u64).to_le_bytes());
+            size = 0xFFFFFFFF;
+            offset = 0xFFFFFFFF;
+        }
+        local_extra.extend_from_slice(entry.extra);
+        local_extra.extend_from_slice(entry.local_extra);
+        central_extra.extend_from_slice(entry.extra);
+
+        let (local_crc, local_size) = if entry.data_descriptor {
+            (0, 0)
+        } else {
+            (crc, size)
+        };
+
+        let d = &mut self.data;
+        d.extend_from_slice(&LFH_SIG.to_le_bytes());
+        d.extend_from_slice(&20u16.to_le_bytes());
+        d.extend_from_slice(&flags.to_le_bytes());
+        d.extend_from_slice(&0u16.to_le_bytes());
+        d.extend_from_slice(&entry.last_mod.1.to_le_bytes());
+        d.extend_from_slice(&entry.last_mod.0.to_le_bytes());
+        d.extend_from_slice(&local_crc.to_le_bytes());
+        d.extend_from_slice(&local_size.to_le_bytes());
+        d.extend_from_slice(&local_size.to_le_bytes());
+        d.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        d.extend_from_slice(&(local_extra.len() as u16).to_le_bytes());
+        d.extend_from_slice(name);
+        d.extend_from_slice(&local_extra);
+        d.extend_from_slice(entry.contents);
+        if entry.data_descriptor {
+            if entry.descriptor_signature {
+                d.extend_from_slice(&DD_SIG.to_le_bytes());
+            }
+            d.extend_from_slice(&crc.to_le_bytes());
+            if entry.zip64 {
+                d.extend_from_slice(&(entry.contents.len() as u64).to_le_bytes());
+                d.extend_from_slice(&(entry.contents.len() as u64).to_le_bytes());
+            } else {
+                d.extend_from_slice(&size.to_le_bytes());
+                d.extend_from_slice(&size.to_le_bytes());
+            }
+        }
+
+        let (version_made_by, external_attributes) = match entry.unix_mode {
+            Some(mode) => (3 << 8 | 20u16, mode << 16 | entry.dos_attributes as u32),
+            None => (20, entry.dos_attributes as u32),
+        };
+        let c = &mut self.central;
+        c.extend_from_slice(&CD_SIG.to_le_bytes());
+        c.extend_from_slice(&version_made_by.to_le_bytes());
+        c.extend_from_slice(&20u16.to_le_bytes());
+        c.extend_from_slice(&flags.to_le_bytes());
+        c.extend_from_slice(&0u16.to_le_bytes());
+        c.extend_from_slice(&entry.last_mod.1.to_le_bytes());
+        c.extend_from_slice(&entry.last_mod.0.to_le_bytes());
+        c.extend_from_slice(&crc.to_le_bytes());
+        c.extend_from_slice(&size.to_le_bytes());
+        c.extend_from_slice(&size.to_le_bytes());
+        c.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        c.extend_from_slice(&(central_extra.len() as u16).to_le_bytes());
+        c.extend_from_slice(&(entry.comment.len() as u16).to_le_bytes());
+        c.extend_from_slice(&0u16.to_le_bytes());
+        c.extend_from_slice(&0u16.to_le_bytes());
+        c.extend_from_slice(&external_attributes.to_le_bytes());
+        c.extend_from_slice(&offset.to_le_bytes());
+        c.extend_from_slice(name);
+        c.extend_from_slice(&central_extra);
+        c.extend_from_slice(entry.comment);
+
+        self.entries += 1;
+        self
+    }
+
+    /// Write the central directory and a regular end of central directory.
+    pub fn finish(&self) -> Vec<u8> {
+        let mut out = self.data.clone();
+        let cd_offset = out.len() as u32;
+        out.extend_from_slice(&self.central);
+        write_eocd(
+            &mut out,
+            0,
+            self.entries as u16,
+            self.central.len() as u32,
+            cd_offset,
+            &self.comment,
+        );
+        [self.prefix.clone(), out].concat()
+    }
+
+    /// Split the archive in two disks, the first with the file data and the
+    /// second with the central directory.
+    pub fn finish_split(&self) -> Vec<Vec<u8>> {
+        let mut last = self.central.clone();
+        write_eocd(
+            &mut last,
+            1,
+            self.entries as u16,
+            self.central.len() as u32,
+            0,
+            &self.comment,
+        );
+        vec![self.data.clone(), last]
+    }
+
+    /// Write the central directory followed by the Zip64 end of central
+    /// directory record and locator, with the regular eocd fields saturated.
+    pub fn finish_zip64(&self) -> Vec<u8> {
+        let mut out = self.data.clone();
+        let cd_offset = out.len() as u64;
+        out.extend_from_slice(&self.central);
+        let eocd64_offset = out.len() as u64;
+
+        out.extend_from_slice(&EOCD64_SIG.to_le_bytes());
+        out.extend_from_slice(&44u64.to_le_bytes());
+        out.extend_from_slice(&45u16.to_le_bytes());
+        out.extend_from_slice(&45u16.to_le_bytes());
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.extend_from_slice(&self.entries.to_le_bytes());
+        out.extend_from_slice(&self.entries.to_le_bytes());
+        out.extend_from_slice(&(self.central.len() as u64).to_le_bytes());
+        out.extend_from_slice(&cd_offset.to_le_bytes());
+
+        out.extend_from_slice(&EOCD64_LOCATOR_SIG.to_le_bytes());
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.extend_from_slice(&eocd64_offset.to_le_bytes());
+        out.extend_from_slice(&1u32.to_le_bytes());
+
+        write_eocd(&mut out, 0, 0xFFFF, 0xFFFFFFFF, 0xFFFFFFFF, &self.comment);
+        [self.prefix.clone(), out].concat()
+    }
+}
+
+/// An Info-ZIP Unicode Path or Comment extra field holding `text`, made for
+/// the header value `header`.
+pub(crate) fn unicode_extra(id: u16, header: &[u8], text: &str) -> Vec<u8> {
+    let mut extra = id.to_le_bytes().to_vec();
+    extra.extend_from_slice(&(5 + text.len() as u16).to_le_bytes());
+    extra.push(1);
+    extra.extend_from_slice(&ZIP_CRC.checksum(header).to_le_bytes());
+    extra.extend_from_slice(text.as_bytes());
+    extra
+}
+
+/// A directory of its own for a test, removed with everything in it when
+/// dropped.
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Create an empty directory named after the test `name`.
+    pub fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("ziplayer-test-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn write_eocd(
+    out: &mut Vec<u8>,
+    disk: u16,
+    entries: u16,
+    cd_size: u32,
+    cd_offset: u32,
+    comment: &[u8],
+) {
+    out.extend_from_slice(&EOCD_SIG.to_le_bytes());
+    out.extend_from_slice(&disk.to_le_bytes());
+    out.extend_from_slice(&disk.to_le_bytes());
+    out.extend_from_slice(&entries.to_le_bytes());
+    out.extend_from_slice(&entries.to_le_bytes());
+    out.extend_from_slice(&cd_size.to_le_bytes());
+    out.extend_from_slice(&cd_offset.to_le_bytes());
+    out.extend_from_slice(&(comment.len() as u16).to_le_bytes());
+    out.extend_from_slice(comment);
+}
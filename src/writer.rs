@@ -21,6 +21,7 @@ use crate::structures::{CentralDirectory, ZipEntry};
 use std::collections::BTreeMap;
 use std::io::Write;
 
+#[allow(dead_code)]
 pub struct ZipWriter<'writer, W: Write> {
     writer: &'writer mut W,
     entries: BTreeMap<String, ZipEntry>,